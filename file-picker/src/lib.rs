@@ -12,19 +12,14 @@ use paging_copy::Paging;
 
 mod paging_copy;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub enum FileType {
     Folder,
     WithExtension(String),
+    #[default]
     Any,
 }
 
-impl Default for FileType {
-    fn default() -> Self {
-        FileType::Any
-    }
-}
-
 pub struct FilePicker<'a> {
     file_type: FileType,
     // items: Vec<String>,
@@ -34,6 +29,9 @@ pub struct FilePicker<'a> {
     theme: &'a dyn Theme,
     max_length: Option<usize>,
     initial_folder: Option<PathBuf>,
+    follow_symlinks: bool,
+    wrap_navigation: bool,
+    default: Option<usize>,
 }
 
 impl Default for FilePicker<'static> {
@@ -58,6 +56,14 @@ impl FilePicker<'_> {
         self
     }
 
+    /// Sets the entry highlighted when the picker opens, in the initial folder.
+    ///
+    /// An index past the last entry is ignored, and the first entry highlighted instead.
+    pub fn default(&mut self, index: usize) -> &mut Self {
+        self.default = Some(index);
+        self
+    }
+
     /// Sets an optional max length for a page.
     ///
     /// Max length is disabled by None
@@ -95,6 +101,25 @@ impl FilePicker<'_> {
         self
     }
 
+    /// Indicates whether 'Space' descends into symlinked folders.
+    ///
+    /// Symlinks are marked with a trailing `@`, as following them may lead into cycles.
+    /// When not followed, 'Space' does nothing on them, and 'Enter' selects them like any folder.
+    /// The default is to follow them.
+    pub fn follow_symlinks(&mut self, val: bool) -> &mut Self {
+        self.follow_symlinks = val;
+        self
+    }
+
+    /// Indicates whether moving past the last entry goes back to the first one, and the other
+    /// way around.
+    ///
+    /// When disabled, the selection stops at the first and last entries. The default is to wrap.
+    pub fn wrap_navigation(&mut self, val: bool) -> &mut Self {
+        self.wrap_navigation = val;
+        self
+    }
+
     /// Indicates whether to report the selected value after interaction.
     ///
     /// The default is to report the selection.
@@ -105,9 +130,13 @@ impl FilePicker<'_> {
 
     /// Enables user interaction and returns the result.
     ///
-    /// The user can select the items with the 'Space' bar or 'Enter' and the index of selected item will be returned.
+    /// The user can descend into the highlighted folder with the 'Space' bar, go back to the parent
+    /// folder with 'Backspace', and select the highlighted entry with 'Enter', which returns its
+    /// path, folders included. Going back highlights the folder just left.
+    /// Pressing 'Space' on a file selects it, as there is nothing to descend into.
+    /// As in vim, 'gg' and 'G' jump to the first and last entries.
     /// The dialog is rendered on stderr.
-    /// Result contains `index` if user selected one of items using 'Enter'.
+    /// Result contains the path if user selected one of items using 'Enter'.
     /// This unlike [`interact_opt`](Self::interact_opt) does not allow to quit with 'Esc' or 'q'.
     #[inline]
     pub fn interact(&self) -> io::Result<PathBuf> {
@@ -116,9 +145,10 @@ impl FilePicker<'_> {
 
     /// Enables user interaction and returns the result.
     ///
-    /// The user can select the items with the 'Space' bar or 'Enter' and the index of selected item will be returned.
+    /// Navigation works as in [`interact`](Self::interact): 'Space' descends into a folder,
+    /// 'Backspace' goes back up, 'Enter' selects the highlighted entry.
     /// The dialog is rendered on stderr.
-    /// Result contains `Some(index)` if user selected one of items using 'Enter' or `None` if user cancelled with 'Esc' or 'q'.
+    /// Result contains `Some(path)` if user selected one of items using 'Enter' or `None` if user cancelled with 'Esc' or 'q'.
     #[inline]
     pub fn interact_opt(&self) -> io::Result<Option<PathBuf>> {
         self.interact_on_opt(&Term::stderr())
@@ -145,7 +175,7 @@ impl FilePicker<'_> {
     #[inline]
     pub fn interact_on(&self, term: &Term) -> io::Result<PathBuf> {
         self._interact_on(term, false)?
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Quit not allowed in this case"))
+            .ok_or_else(|| io::Error::other("Quit not allowed in this case"))
     }
 
     /// Like [`interact_opt`](Self::interact_opt) but allows a specific terminal to be set.
@@ -176,26 +206,66 @@ impl FilePicker<'_> {
 
     /// Like `interact` but allows a specific terminal to be set.
     fn _interact_on(&self, term: &Term, allow_quit: bool) -> io::Result<Option<PathBuf>> {
+        // Without a terminal, read_key never blocks and the loop would spin forever.
+        if !term.is_term() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "The file picker needs an interactive terminal",
+            ));
+        }
+
         let mut directory = match &self.initial_folder {
             Some(folder) => folder.clone(),
             None => std::env::current_dir()?,
         };
 
+        // Dropped last, once the menu is cleared and the cursor shown again.
+        let _interrupt_guard = InterruptGuard::install()?;
+        let _cursor_guard = CursorGuard::hide(term)?;
+        // The entry to highlight when showing the next folder.
+        let mut default = self.default;
+        // The folders descended from, with the entry that was highlighted in each.
+        let mut parents: Vec<(PathBuf, usize)> = Vec::new();
+
         'directory: loop {
             let files_in_dir = FilePicker::list_files_in_folder(&directory, &self.file_type)?;
             let filenames: Vec<String> = files_in_dir
                 .iter()
                 .map(|path| {
-                    path.file_name()
+                    let filename = path
+                        .file_name()
                         .expect("Filename existance checked in list function")
-                        .to_string_lossy()
-                        .into()
+                        .to_string_lossy();
+                    if path.is_symlink() {
+                        format!("{filename}@")
+                    } else {
+                        filename.into()
+                    }
                 })
                 .collect();
 
+            // Leaves room on the line for the prompt and the paging info.
+            let prompt_width = self
+                .prompt
+                .as_ref()
+                .map_or(0, |prompt| prompt.chars().count());
+            let max_width = (term.size().1 as usize).saturating_sub(prompt_width + 20);
+            let location = truncate_left(&directory.display().to_string(), max_width.max(10));
+            let header = match &self.prompt {
+                Some(prompt) => format!("{prompt} [{location}]"),
+                None => location,
+            };
+
             let mut paging = Paging::new(term, filenames.len(), self.max_length);
             let mut render = TermThemeRenderer::new(term, self.theme);
-            let mut sel = 0;
+            let mut sel = default
+                .take()
+                .filter(|&index| index < filenames.len())
+                .unwrap_or(0);
+            // Opens on the page showing the selection.
+            paging.current_page = sel / paging.capacity;
+            // The first 'g' of a 'gg'.
+            let mut pending_g = false;
 
             let mut size_vec = Vec::new();
 
@@ -208,13 +278,8 @@ impl FilePicker<'_> {
                 size_vec.push(*size);
             }
 
-            term.hide_cursor()?;
-
             loop {
-                if let Some(ref prompt) = self.prompt {
-                    paging
-                        .render_prompt(|paging_info| render.select_prompt(prompt, paging_info))?;
-                }
+                paging.render_prompt(|paging_info| render.select_prompt(&header, paging_info))?;
 
                 for (idx, item) in filenames
                     .iter()
@@ -227,84 +292,107 @@ impl FilePicker<'_> {
 
                 term.flush()?;
 
-                match term.read_key()? {
+                let key = match term.read_key() {
+                    Ok(_) if InterruptGuard::is_interrupted() => {
+                        render.clear()?;
+                        return Err(io::ErrorKind::Interrupted.into());
+                    }
+                    Ok(key) => key,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                        // Ctrl-C: wipe the menu before bailing out, the guards restore the cursor
+                        // and then the interruption.
+                        render.clear()?;
+                        return Err(err);
+                    }
+                    Err(err) => return Err(err),
+                };
+
+                let is_g_pending = std::mem::replace(&mut pending_g, false);
+                match key {
+                    Key::Char('g') if is_g_pending && !filenames.is_empty() => sel = 0,
+                    Key::Char('g') => pending_g = true,
+                    Key::Char('G') if !filenames.is_empty() => sel = filenames.len() - 1,
                     Key::ArrowDown | Key::Tab | Key::Char('j') => {
                         if sel == !0 {
                             sel = 0;
-                        } else {
+                        } else if self.wrap_navigation {
                             sel = (sel as u64 + 1).rem(filenames.len() as u64) as usize;
+                        } else {
+                            sel = (sel + 1).min(filenames.len().saturating_sub(1));
                         }
                     }
-                    Key::Escape | Key::Char('q') => {
-                        if allow_quit {
-                            if self.clear {
-                                render.clear()?;
-                            } else {
-                                term.clear_last_lines(paging.capacity)?;
-                            }
+                    Key::Escape | Key::Char('q') if allow_quit => {
+                        if self.clear {
+                            render.clear()?;
+                        } else {
+                            term.clear_last_lines(paging.capacity)?;
+                        }
 
-                            term.show_cursor()?;
-                            term.flush()?;
+                        term.show_cursor()?;
+                        term.flush()?;
 
-                            return Ok(None);
-                        }
+                        return Ok(None);
                     }
                     Key::ArrowUp | Key::BackTab | Key::Char('k') => {
                         if sel == !0 {
                             sel = filenames.len() - 1;
-                        } else {
+                        } else if self.wrap_navigation {
                             sel = ((sel as i64 - 1 + filenames.len() as i64)
                                 % (filenames.len() as i64))
                                 as usize;
+                        } else {
+                            sel = sel.saturating_sub(1);
                         }
                     }
-                    Key::ArrowLeft | Key::Char('h') => {
-                        if paging.active {
-                            sel = paging.previous_page();
-                        }
+                    Key::ArrowLeft | Key::Char('h') if paging.active => {
+                        sel = paging.previous_page();
                     }
-                    Key::ArrowRight | Key::Char('l') => {
-                        if paging.active {
-                            sel = paging.next_page();
-                        }
+                    Key::ArrowRight | Key::Char('l') if paging.active => {
+                        sel = paging.next_page();
                     }
 
-                    Key::Enter if sel != !0 => {
-                        if self.clear {
-                            render.clear()?;
-                        }
-
-                        if let Some(ref prompt) = self.prompt {
-                            if self.report {
-                                render.select_prompt_selection(prompt, &filenames[sel])?;
+                    Key::Backspace if has_parent(&directory) => {
+                        let parent = directory.parent().expect("Checked above").to_path_buf();
+                        render.clear()?;
+                        default = match parents.pop() {
+                            Some((folder, index)) if folder == parent => Some(index),
+                            // Above the initial folder, or the stack no longer matches.
+                            _ => {
+                                parents.clear();
+                                FilePicker::list_files_in_folder(&parent, &self.file_type)?
+                                    .iter()
+                                    .position(|path| *path == directory)
                             }
-                        }
+                        };
+                        directory = parent;
+                        continue 'directory;
+                    }
+                    Key::Enter | Key::Char(' ') if sel != !0 => {
+                        let path = &files_in_dir[sel];
+                        match confirmation(&key, path.is_dir(), self.can_descend(path)) {
+                            Some(Confirmation::Descend) => {
+                                render.clear()?;
+                                parents.push((directory, sel));
+                                directory = files_in_dir[sel].clone();
+                                continue 'directory;
+                            }
+                            Some(Confirmation::Select) => {
+                                if self.clear {
+                                    render.clear()?;
+                                }
 
-                        term.show_cursor()?;
-                        term.flush()?;
+                                if let Some(ref prompt) = self.prompt {
+                                    if self.report {
+                                        render.select_prompt_selection(prompt, &filenames[sel])?;
+                                    }
+                                }
 
-                        return Ok(Some(files_in_dir[sel].clone()));
-                    }
-                    Key::Char(' ') if sel != !0 => {
-                        if self.clear {
-                            render.clear()?;
-                        }
+                                term.show_cursor()?;
+                                term.flush()?;
 
-                        if let Some(ref prompt) = self.prompt {
-                            if self.report {
-                                render.select_prompt_selection(prompt, &filenames[sel])?;
+                                return Ok(Some(files_in_dir[sel].clone()));
                             }
-                        }
-                        let current = &files_in_dir[sel];
-                        if current.is_dir() {
-                            render.clear()?;
-                            directory = current.clone();
-                            continue 'directory;
-                        } else {
-                            term.show_cursor()?;
-                            term.flush()?;
-
-                            return Ok(Some(files_in_dir[sel].clone()));
+                            None => {}
                         }
                     }
                     _ => {}
@@ -321,6 +409,10 @@ impl FilePicker<'_> {
         }
     }
 
+    fn can_descend(&self, path: &Path) -> bool {
+        path.is_dir() && (self.follow_symlinks || !path.is_symlink())
+    }
+
     fn list_files_in_folder(folder: &Path, file_type: &FileType) -> io::Result<Vec<PathBuf>> {
         fn entry_match(entry: &Path, file_type: &FileType) -> bool {
             if entry.file_name().is_none() {
@@ -380,6 +472,132 @@ impl<'a> FilePicker<'a> {
             max_length: None,
             theme,
             initial_folder: None,
+            follow_symlinks: true,
+            wrap_navigation: true,
+            default: None,
+        }
+    }
+}
+
+/// What Enter or Space do to the highlighted entry.
+#[derive(Debug, PartialEq, Eq)]
+enum Confirmation {
+    Descend,
+    Select,
+}
+
+/// Space opens a folder and Enter selects it, both select a file. Space does nothing on a folder
+/// that can't be opened, like a symlink when they aren't followed.
+fn confirmation(key: &Key, is_folder: bool, can_descend: bool) -> Option<Confirmation> {
+    match key {
+        Key::Char(' ') if can_descend => Some(Confirmation::Descend),
+        Key::Char(' ') if is_folder => None,
+        Key::Enter | Key::Char(' ') => Some(Confirmation::Select),
+        _ => None,
+    }
+}
+
+/// Whether there is a folder to go back to, a relative path ending at its first component.
+fn has_parent(directory: &Path) -> bool {
+    directory
+        .parent()
+        .is_some_and(|parent| !parent.as_os_str().is_empty())
+}
+
+/// Keeps the end of `text`, the most telling part of a path, if it is longer than `max_width`.
+fn truncate_left(text: &str, max_width: usize) -> String {
+    let length = text.chars().count();
+    if length <= max_width {
+        return text.to_string();
+    }
+
+    let end: String = text.chars().skip(length - max_width + 1).collect();
+    format!("…{end}")
+}
+
+/// Hides the cursor for as long as it is alive.
+///
+/// Showing the cursor again on drop guarantees the terminal is left usable,
+/// even when the interaction is aborted with an error or a Ctrl-C.
+struct CursorGuard<'a> {
+    term: &'a Term,
+}
+
+impl<'a> CursorGuard<'a> {
+    fn hide(term: &'a Term) -> io::Result<CursorGuard<'a>> {
+        term.hide_cursor()?;
+        Ok(CursorGuard { term })
+    }
+}
+
+impl Drop for CursorGuard<'_> {
+    fn drop(&mut self) {
+        let _ = self.term.show_cursor();
+        let _ = self.term.flush();
+    }
+}
+
+/// Whether a Ctrl-C came while an [`InterruptGuard`] was installed.
+#[cfg(unix)]
+static INTERRUPTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Holds back Ctrl-C for as long as it is alive, raising it again on drop.
+///
+/// On a Ctrl-C, `read_key` raises `SIGINT` before returning, which would otherwise kill the
+/// process then and there, leaving the menu on screen and the cursor hidden.
+struct InterruptGuard {
+    #[cfg(unix)]
+    previous: libc::sigaction,
+}
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, std::sync::atomic::Ordering::SeqCst);
+}
+
+impl InterruptGuard {
+    #[cfg(unix)]
+    fn install() -> io::Result<InterruptGuard> {
+        INTERRUPTED.store(false, std::sync::atomic::Ordering::SeqCst);
+        // SAFETY: the handler only stores to an atomic, and the previous action is restored on
+        // drop.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            if libc::sigaction(libc::SIGINT, &action, &mut previous) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(InterruptGuard { previous })
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn install() -> io::Result<InterruptGuard> {
+        Ok(InterruptGuard {})
+    }
+
+    #[cfg(unix)]
+    fn is_interrupted() -> bool {
+        INTERRUPTED.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    #[cfg(not(unix))]
+    fn is_interrupted() -> bool {
+        false
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        // SAFETY: puts back the action replaced in `install`.
+        #[cfg(unix)]
+        unsafe {
+            libc::sigaction(libc::SIGINT, &self.previous, std::ptr::null_mut());
+            if InterruptGuard::is_interrupted() {
+                libc::raise(libc::SIGINT);
+            }
         }
     }
 }
@@ -410,7 +628,7 @@ impl<'a> TermThemeRenderer<'a> {
         f: F,
     ) -> io::Result<()> {
         let mut buf = String::new();
-        f(self, &mut buf).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        f(self, &mut buf).map_err(io::Error::other)?;
         self.height += buf.chars().filter(|&x| x == '\n').count() + 1;
         self.term.write_line(&buf)
     }
@@ -481,3 +699,59 @@ impl<'a> TermThemeRenderer<'a> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn space_descends_into_a_folder() {
+        assert_eq!(
+            confirmation(&Key::Char(' '), true, true),
+            Some(Confirmation::Descend)
+        );
+    }
+
+    #[test]
+    fn enter_selects_a_folder() {
+        assert_eq!(
+            confirmation(&Key::Enter, true, true),
+            Some(Confirmation::Select)
+        );
+    }
+
+    #[test]
+    fn space_doesnt_select_a_folder_it_cant_open() {
+        assert_eq!(confirmation(&Key::Char(' '), true, false), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn only_enter_selects_a_symlinked_folder_not_followed() {
+        let folder = std::env::temp_dir().join(format!("file-picker-{}", std::process::id()));
+        fs::create_dir_all(folder.join("target")).unwrap();
+        let link = folder.join("link");
+        let _ = fs::remove_file(&link);
+        std::os::unix::fs::symlink(folder.join("target"), &link).unwrap();
+
+        let mut picker = FilePicker::new(FileType::Any);
+        picker.follow_symlinks(false);
+        let can_descend = picker.can_descend(&link);
+        let is_folder = link.is_dir();
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert!(!can_descend);
+        assert_eq!(confirmation(&Key::Char(' '), is_folder, can_descend), None);
+        assert_eq!(
+            confirmation(&Key::Enter, is_folder, can_descend),
+            Some(Confirmation::Select)
+        );
+    }
+
+    #[test]
+    fn enter_and_space_select_a_file() {
+        for key in [Key::Enter, Key::Char(' ')] {
+            assert_eq!(confirmation(&key, false, false), Some(Confirmation::Select));
+        }
+    }
+}
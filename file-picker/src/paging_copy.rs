@@ -23,7 +23,7 @@ impl<'a> Paging<'a> {
         let term_size = term.size();
         // Subtract -2 because we need space to render the prompt, if paging is active
         let capacity = max_capacity
-            .unwrap_or(std::usize::MAX)
+            .unwrap_or(usize::MAX)
             .min(term_size.0 as usize)
             // Safeguard in case term_size or max_length is 2 or less. Guarantees no unwanted wrapping behavior.
             .max(3)
@@ -52,7 +52,7 @@ impl<'a> Paging<'a> {
             self.current_term_size = new_term_size;
             self.capacity = self
                 .max_capacity
-                .unwrap_or(std::usize::MAX)
+                .unwrap_or(usize::MAX)
                 .min(self.current_term_size.0 as usize)
                 .max(3)
                 - 2;
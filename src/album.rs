@@ -1,86 +1,221 @@
-use anyhow::Result;
-use dialoguer::{theme::ColorfulTheme, Select};
+use anyhow::{bail, Result};
+#[cfg(feature = "interactive")]
+use dialoguer::{theme::Theme, Confirm};
+use std::{fs, path::Path};
 
-use crate::api::{Album, AlbumsListRequest, AlbumsListResponse, Api, SharedAlbumsListResponse};
+use crate::{
+    api::{
+        Album, AlbumsListRequest, AlbumsListResponse, Api, ApiAlbum, Id, SharedAlbumsListResponse,
+    },
+    client::http_client,
+};
+#[cfg(feature = "interactive")]
+use crate::{
+    menu,
+    output::{warning, Spinner},
+};
 
-pub async fn pick_album(api: &Api) -> Result<Album> {
-    let album_types = &["Private albums", "Shared albums", "Cancel"];
-    let selection = Select::with_theme(&ColorfulTheme::default())
-        .with_prompt("Select an album")
-        .default(0)
-        .items(album_types)
-        .interact()?;
+pub const COVER_FILE: &str = "_cover.jpg";
+
+/// Lets the user pick an album, either private or shared, or `None` if they cancelled.
+///
+/// With `flatten`, both kinds are fetched and offered in a single list, each album tagged
+/// with its kind, saving a menu step.
+#[cfg(feature = "interactive")]
+pub async fn pick_album(api: &Api, theme: &dyn Theme, flatten: bool) -> Result<Option<Album>> {
+    let mut albums = if flatten {
+        let _spinner = Spinner::start("Fetching albums...");
+        let mut albums = list_albums(api).await?;
+        albums.extend(list_shared_albums(api).await?);
+        albums
+    } else {
+        let album_types = &["Private albums", "Shared albums"];
+        let shared = match menu::select(theme, "Select an album", album_types)? {
+            Some(index) => index == 1,
+            None => return Ok(None),
+        };
+        let _spinner = Spinner::start("Fetching albums...");
+        if shared {
+            list_shared_albums(api).await?
+        } else {
+            list_albums(api).await?
+        }
+    };
 
-    let mut albums = match selection {
-        0 => list_albums(api).await,
-        1 => list_shared_albums(api).await,
-        _ => unreachable!("Only two choices"),
-    }?;
+    let album_names: Vec<_> = albums
+        .iter()
+        .map(|album| {
+            if !flatten {
+                album.title.clone()
+            } else if album.shared {
+                format!("{} [shared]", album.title)
+            } else {
+                format!("{} [private]", album.title)
+            }
+        })
+        .collect();
 
-    let album_names: Vec<_> = albums.iter().map(|album| &album.title).collect();
+    let album = match menu::select_filtered(theme, "Select an album", &album_names)? {
+        Some(selection) => albums.swap_remove(selection),
+        None => return Ok(None),
+    };
 
-    let selection = Select::with_theme(&ColorfulTheme::default())
-        .with_prompt("Select an album")
-        .default(0)
-        .items(&album_names)
+    println!("{}: {}", album.title, album.product_url);
+    let should_open = Confirm::with_theme(theme)
+        .with_prompt("Open it in a browser to check it?")
+        .default(false)
         .interact()?;
+    if should_open {
+        if let Err(e) = webbrowser::open(&album.product_url) {
+            warning!("couldn't open a browser: {e}");
+        }
+    }
 
-    let album = albums.swap_remove(selection);
-    Ok(album)
+    Ok(Some(album))
 }
 
-async fn list_shared_albums(api: &Api) -> Result<Vec<Album>> {
-    let album_response: SharedAlbumsListResponse = api
-        .get(
-            "https://photoslibrary.googleapis.com/v1/sharedAlbums",
-            &AlbumsListRequest::default(),
-        )
+/// The kind of albums to pick from on the command line.
+#[derive(Clone, Copy, clap::ArgEnum)]
+pub enum AlbumKind {
+    Private,
+    Shared,
+}
+
+/// An album chosen on the command line rather than in a menu.
+pub enum AlbumChoice {
+    /// Counting from 1, in the order of the menu.
+    Index(usize),
+    Title(String),
+}
+
+/// Finds the album chosen on the command line, among the albums of this kind.
+pub async fn find_album(api: &Api, kind: AlbumKind, choice: &AlbumChoice) -> Result<Album> {
+    let mut albums = match kind {
+        AlbumKind::Private => list_albums(api).await?,
+        AlbumKind::Shared => list_shared_albums(api).await?,
+    };
+
+    match choice {
+        AlbumChoice::Index(index) => {
+            if *index == 0 || *index > albums.len() {
+                bail!(
+                    "No album at index {index}, there are {} albums, counting from 1",
+                    albums.len()
+                );
+            }
+            Ok(albums.swap_remove(index - 1))
+        }
+        AlbumChoice::Title(title) => {
+            let matching: Vec<_> = albums
+                .iter()
+                .enumerate()
+                .filter(|(_, album)| album.title.trim() == title.trim())
+                .map(|(index, _)| index)
+                .collect();
+            match matching[..] {
+                [index] => Ok(albums.swap_remove(index)),
+                [] => bail!("No album titled {title}"),
+                _ => bail!(
+                    "{} albums are titled {title}, pick one with --album-index",
+                    matching.len()
+                ),
+            }
+        }
+    }
+}
+
+/// Prints every album, private and shared, to find their id or check them on the web.
+pub async fn print_albums(api: &Api) -> Result<()> {
+    let albums = list_albums(api).await?;
+    let shared_albums = list_shared_albums(api).await?;
+
+    for album in albums.iter().chain(&shared_albums) {
+        let kind = if album.shared { "shared" } else { "private" };
+        println!("{} [{kind}] {}", album.id, album.title);
+        println!("    {}", album.product_url);
+    }
+
+    Ok(())
+}
+
+/// Downloads the cover of an album into `folder`, doing nothing for albums without one.
+///
+/// Once sized, Google serves the cover as a JPEG.
+pub async fn download_cover(api: &Api, album_id: &Id, folder: &Path) -> Result<()> {
+    let cover_url = match api.get_album(album_id).await?.cover_photo_base_url {
+        Some(cover_url) => cover_url,
+        None => return Ok(()),
+    };
+
+    let cover = http_client()?
+        .get(format!("{cover_url}=w1024-h1024"))
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
         .await?;
+    fs::write(folder.join(COVER_FILE), cover)?;
 
-    if let Some(albums) = album_response.shared_albums {
-        Ok(albums
-            .into_iter()
-            .filter_map(|album| {
-                if let Some(title) = album.title {
-                    Some(Album {
-                        id: album.id,
-                        title,
-                        product_url: album.product_url,
-                    })
-                } else {
-                    None
-                }
-            })
-            .collect())
-    } else {
-        Ok(vec![])
+    Ok(())
+}
+
+async fn list_shared_albums(api: &Api) -> Result<Vec<Album>> {
+    let mut albums = vec![];
+    let mut request = AlbumsListRequest::default();
+
+    loop {
+        let album_response: SharedAlbumsListResponse = api
+            .get(
+                "https://photoslibrary.googleapis.com/v1/sharedAlbums",
+                &request,
+            )
+            .await?;
+
+        if let Some(shared_albums) = album_response.shared_albums {
+            albums.extend(
+                shared_albums
+                    .into_iter()
+                    .filter_map(|album| to_album(album, true)),
+            );
+        }
+
+        match album_response.next_page_token {
+            Some(next_page_token) => request.page_token = Some(next_page_token),
+            None => return Ok(albums),
+        }
     }
 }
 
 async fn list_albums(api: &Api) -> Result<Vec<Album>> {
-    let album_response: AlbumsListResponse = api
-        .get(
-            "https://photoslibrary.googleapis.com/v1/albums",
-            &AlbumsListRequest::default(),
-        )
-        .await?;
+    let mut albums = vec![];
+    let mut request = AlbumsListRequest::default();
 
-    if let Some(albums) = album_response.albums {
-        Ok(albums
-            .into_iter()
-            .filter_map(|album| {
-                if let Some(title) = album.title {
-                    Some(Album {
-                        id: album.id,
-                        title,
-                        product_url: album.product_url,
-                    })
-                } else {
-                    None
-                }
-            })
-            .collect())
-    } else {
-        Ok(vec![])
+    loop {
+        let album_response: AlbumsListResponse = api
+            .get("https://photoslibrary.googleapis.com/v1/albums", &request)
+            .await?;
+
+        if let Some(private_albums) = album_response.albums {
+            albums.extend(
+                private_albums
+                    .into_iter()
+                    .filter_map(|album| to_album(album, false)),
+            );
+        }
+
+        match album_response.next_page_token {
+            Some(next_page_token) => request.page_token = Some(next_page_token),
+            None => return Ok(albums),
+        }
     }
 }
+
+/// Albums without a title can't be told apart in a menu, they are left out.
+fn to_album(album: ApiAlbum, shared: bool) -> Option<Album> {
+    album.title.map(|title| Album {
+        id: album.id,
+        title,
+        product_url: album.product_url,
+        shared,
+    })
+}
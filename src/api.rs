@@ -1,7 +1,12 @@
 use anyhow::Result;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
 use reqwest::Client;
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
 use std::{fmt::Display, ops::Deref};
+use tokio::sync::Mutex;
+use yup_oauth2::{authenticator::DefaultAuthenticator, AccessToken};
+
+use crate::error::SyncError;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Id(pub String);
@@ -14,11 +19,18 @@ impl Deref for Id {
     }
 }
 
+impl Display for Id {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Debug)]
 pub struct Album {
     pub id: Id,
     pub title: String,
     pub product_url: String,
+    pub shared: bool,
 }
 
 impl Display for Album {
@@ -33,6 +45,19 @@ pub struct ApiAlbum {
     pub id: Id,
     pub title: Option<String>,
     pub product_url: String,
+    /// Missing for empty albums.
+    pub cover_photo_base_url: Option<String>,
+    /// A number, as a string. Missing for empty albums too.
+    pub media_items_count: Option<String>,
+}
+
+impl ApiAlbum {
+    pub fn media_items_count(&self) -> usize {
+        self.media_items_count
+            .as_deref()
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(0)
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -52,27 +77,179 @@ pub struct SharedAlbumsListResponse {
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AlbumsListRequest {
-    page_size: Option<u32>,
-    page_token: Option<String>,
+    pub page_size: Option<u32>,
+    pub page_token: Option<String>,
     exclude_non_app_created_data: bool,
 }
 
 impl Default for AlbumsListRequest {
     fn default() -> Self {
         Self {
-            page_size: Some(20),
+            page_size: Some(50),
             page_token: None,
             exclude_non_app_created_data: false,
         }
     }
 }
 
+/// The most media items Google returns in a page, fewer pages means fewer round trips.
+pub const MEDIA_ITEMS_PAGE_SIZE: u32 = 100;
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MediaItemSearchRequest<'a> {
-    pub album_id: &'a Id,
+    /// Without an album id, the whole library is searched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album_id: Option<&'a Id>,
     pub page_size: Option<u32>,
     pub page_token: Option<String>,
+    /// Google refuses filters in conjunction with an album id.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filters: Option<&'a Filters>,
+    /// Only accepted along with a date filter.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order_by: Option<&'static str>,
+}
+
+/// The order of the items of a library search, by creation time.
+#[derive(Debug, Clone, Copy, clap::ArgEnum)]
+pub enum Order {
+    Oldest,
+    Newest,
+}
+
+impl Order {
+    pub fn order_by(self) -> &'static str {
+        match self {
+            Order::Oldest => "MediaMetadata.creation_time",
+            Order::Newest => "MediaMetadata.creation_time desc",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Filters {
+    pub include_archived_media: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feature_filter: Option<FeatureFilter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_filter: Option<ContentFilter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date_filter: Option<DateFilter>,
+}
+
+impl Filters {
+    /// Whether the filters differ from Google's defaults.
+    pub fn is_empty(&self) -> bool {
+        !self.include_archived_media
+            && self.feature_filter.is_none()
+            && self.content_filter.is_none()
+            && self.date_filter.is_none()
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DateFilter {
+    pub ranges: Vec<DateRange>,
+}
+
+impl DateFilter {
+    /// Only keeps the items created from `start` onwards.
+    pub fn since(start: NaiveDate) -> Self {
+        Self::between(Some(start), None)
+    }
+
+    /// Only keeps the items created between `start` and `end` included, a missing bound
+    /// leaving the range open.
+    pub fn between(start: Option<NaiveDate>, end: Option<NaiveDate>) -> Self {
+        // Google doesn't allow open ranges, the year 1 is the earliest it accepts.
+        let start = start.unwrap_or_else(|| NaiveDate::from_ymd_opt(1, 1, 1).expect("Valid date"));
+        let end = end.unwrap_or_else(|| Utc::now().date_naive());
+
+        Self {
+            ranges: vec![DateRange {
+                start_date: start.into(),
+                end_date: end.into(),
+            }],
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DateRange {
+    pub start_date: Date,
+    pub end_date: Date,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl From<NaiveDate> for Date {
+    fn from(date: NaiveDate) -> Self {
+        Self {
+            year: date.year(),
+            month: date.month(),
+            day: date.day(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FeatureFilter {
+    pub included_features: Vec<Feature>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Feature {
+    Favorites,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentFilter {
+    pub included_content_categories: Vec<ContentCategory>,
+}
+
+/// The categories Google sorts media into, see
+/// https://developers.google.com/photos/library/reference/rest/v1/mediaItems/search#contentcategory
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, clap::ArgEnum)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ContentCategory {
+    Animals,
+    Arts,
+    Birthdays,
+    Cityscapes,
+    Crafts,
+    Documents,
+    Fashion,
+    Flowers,
+    Food,
+    Gardens,
+    Holidays,
+    Houses,
+    Landmarks,
+    Landscapes,
+    Night,
+    People,
+    Performances,
+    Pets,
+    Receipts,
+    Screenshots,
+    Selfies,
+    Sport,
+    Travel,
+    Utility,
+    Weddings,
+    Whiteboards,
 }
 
 #[derive(Debug, Deserialize)]
@@ -81,23 +258,63 @@ pub struct MediaItem {
     pub id: Id,
     pub filename: String,
     pub base_url: String,
+    pub product_url: String,
     pub media_metadata: MediaMetadata,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct MediaMetadata {
+    pub creation_time: Option<DateTime<Utc>>,
+    #[serde(
+        default,
+        deserialize_with = "int64_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub width: Option<u32>,
+    #[serde(
+        default,
+        deserialize_with = "int64_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub photo: Option<Photo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub video: Option<Video>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
-pub struct Photo {}
+pub struct Photo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera_make: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera_model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub focal_length: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aperture_f_number: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iso_equivalent: Option<u32>,
+    /// A duration in seconds, like `"0.008s"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exposure_time: Option<String>,
+}
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
-pub struct Video {}
+pub struct Video {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera_make: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera_model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fps: Option<f64>,
+    /// Whether the video is ready to be downloaded, `READY` once processed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+}
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -106,36 +323,144 @@ pub struct MediaItemResponse {
     pub next_page_token: Option<String>,
 }
 
+/// A search response with only the ids of the items, see [`MEDIA_ITEM_IDS_FIELDS`].
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaItemIdsResponse {
+    pub media_items: Option<Vec<MediaItemId>>,
+    pub next_page_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MediaItemId {
+    pub id: Id,
+}
+
+/// Asks Google for the ids alone when searching, a fraction of the full response.
+pub const MEDIA_ITEM_IDS_FIELDS: &str = "mediaItems(id),nextPageToken";
+
+/// The most items `mediaItems:batchGet` accepts at once.
+pub const BATCH_GET_SIZE: usize = 50;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchGetResponse {
+    media_item_results: Option<Vec<MediaItemResult>>,
+}
+
+/// Either the item, or why Google couldn't get it.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MediaItemResult {
+    media_item: Option<MediaItem>,
+}
+
+/// Google encodes its int64 fields as JSON strings.
+fn int64_string<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| value.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+pub const SCOPES: &[&str] = &["https://www.googleapis.com/auth/photoslibrary.readonly"];
+
 pub struct Api {
     client: Client,
+    auth: DefaultAuthenticator,
+    /// Reused until it is about to expire, not to ask the authenticator on every request.
+    token: Mutex<AccessToken>,
 }
 
 impl Api {
-    pub fn new(client: Client) -> Self {
-        Self { client }
+    pub fn new(client: Client, auth: DefaultAuthenticator, token: AccessToken) -> Self {
+        Self {
+            client,
+            auth,
+            token: Mutex::new(token),
+        }
+    }
+
+    /// The access token, refreshed a minute before it expires.
+    async fn token(&self) -> Result<String, SyncError> {
+        let mut token = self.token.lock().await;
+        if token.is_expired() {
+            *token = self.auth.token(SCOPES).await?;
+        }
+        Ok(token.as_str().to_string())
+    }
+
+    /// Fetches an album by its id, be it private or shared.
+    pub async fn get_album(&self, album_id: &Id) -> Result<ApiAlbum, SyncError> {
+        self.get(
+            &format!("https://photoslibrary.googleapis.com/v1/albums/{album_id}"),
+            &(),
+        )
+        .await
+    }
+
+    /// Fetches the items with these ids, with fresh base URLs, [`BATCH_GET_SIZE`] per request.
+    ///
+    /// The items Google can't find anymore are left out.
+    pub async fn batch_get(&self, ids: &[Id]) -> Result<Vec<MediaItem>, SyncError> {
+        let mut media_items = Vec::with_capacity(ids.len());
+        for ids in ids.chunks(BATCH_GET_SIZE) {
+            let query: Vec<_> = ids.iter().map(|id| ("mediaItemIds", id.as_str())).collect();
+            let response: BatchGetResponse = self
+                .get(
+                    "https://photoslibrary.googleapis.com/v1/mediaItems:batchGet",
+                    &query,
+                )
+                .await?;
+            media_items.extend(
+                response
+                    .media_item_results
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|result| result.media_item),
+            );
+        }
+
+        Ok(media_items)
     }
 
-    pub async fn get<Body, Out>(&self, url: &str, body: &Body) -> Result<Out>
+    pub async fn get<Body, Out>(&self, url: &str, body: &Body) -> Result<Out, SyncError>
     where
         Body: Serialize,
         Out: DeserializeOwned,
     {
-        let response = self.client.get(url).query(&body).send().await?;
+        let response = self
+            .client
+            .get(url)
+            .bearer_auth(self.token().await?)
+            .query(&body)
+            .send()
+            .await?
+            .error_for_status()?;
 
-        let output: Out = response.json().await?;
+        let output: Out = serde_json::from_slice(&response.bytes().await?)?;
         Ok(output)
     }
 
-    pub async fn post<Body, Out>(&self, url: &str, body: &Body) -> Result<Out>
+    pub async fn post<Body, Out>(&self, url: &str, body: &Body) -> Result<Out, SyncError>
     where
         Body: Serialize,
         Out: DeserializeOwned,
     {
         let body = serde_json::to_string(body)?;
 
-        let response = self.client.post(url).body(body).send().await?;
+        let response = self
+            .client
+            .post(url)
+            .bearer_auth(self.token().await?)
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
 
-        let output: Out = response.json().await?;
+        let output: Out = serde_json::from_slice(&response.bytes().await?)?;
         Ok(output)
     }
 }
@@ -1,5 +1,393 @@
-#[derive(clap::Parser)]
+use chrono::NaiveDate;
+use clap_complete::Shell;
+use std::{ops::RangeInclusive, path::PathBuf, time::Duration};
+
+use crate::{
+    album::{AlbumChoice, AlbumKind},
+    api::{ContentCategory, Order},
+    item::{Resolution, UnknownMedia},
+    theme::ThemeChoice,
+};
+
+/// Mirror Google Photos albums into local folders
+#[derive(Clone, clap::Parser)]
+#[clap(version, about)]
 pub struct Cli {
+    /// Open the configuration menu, to list or add synchronized albums
     #[clap(short, long)]
     pub configure: bool,
+    /// When adding an album, list the private and shared albums together
+    #[clap(long)]
+    pub flatten: bool,
+    /// The look of the interactive menus
+    #[clap(long, arg_enum, default_value = "colorful")]
+    pub theme: ThemeChoice,
+    /// Only print the errors, for scripts and cron jobs
+    #[clap(short, long)]
+    pub quiet: bool,
+    /// The User-Agent sent to Google, sync-google-photo/<version> by default
+    #[clap(long, value_name = "USER_AGENT")]
+    pub user_agent: Option<String>,
+    /// Sign in with a refresh token or service account key file instead of the browser, for servers
+    #[clap(long, value_name = "FILE")]
+    pub credentials: Option<PathBuf>,
+    /// Sign in through the browser again, for another account or after the scopes changed
+    #[clap(long, conflicts_with = "credentials")]
+    pub force_reauth: bool,
+    /// Don't use colors in the interactive menus, same as --theme simple
+    #[clap(long)]
+    pub no_color: bool,
+    /// Only synchronize the album with this id, adding it to the configuration if needed
+    #[clap(long, value_name = "ID")]
+    pub album_id: Option<String>,
+    /// With --album-id, download into this folder for this run only, leaving the configuration alone
+    #[clap(long, value_name = "DIR", requires = "album-id")]
+    pub output: Option<PathBuf>,
+    /// Add an album to synchronize, picked with --album-title or --album-index, or else from a menu
+    #[clap(long)]
+    pub add_album: bool,
+    /// When adding an album, show where it would go without saving the configuration
+    #[clap(long)]
+    pub dry_run: bool,
+    /// The kind of album --album-title and --album-index pick from
+    #[clap(long, arg_enum, default_value = "private")]
+    pub album_type: AlbumKind,
+    /// Pick the album at this position, counting from 1, in the order of the menu
+    #[clap(long, value_name = "N", conflicts_with = "album-title")]
+    pub album_index: Option<usize>,
+    /// Pick the album with this title
+    #[clap(long, value_name = "TITLE")]
+    pub album_title: Option<String>,
+    /// Synchronize the whole library instead of the configured albums
+    #[clap(long)]
+    pub all_library: bool,
+    /// Only list the item ids, then download the items missing from the folders, quicker on large albums
+    #[clap(long, conflicts_with = "sequence-prefix")]
+    pub download_missing_only: bool,
+    /// Rename the already downloaded photos to match the current naming scheme
+    #[clap(long)]
+    pub reorganize: bool,
+    /// Sign in if needed and check that Google accepts the credentials
+    #[clap(long)]
+    pub check_auth: bool,
+    /// Compare the albums with their folders, listing the missing and extra files
+    #[clap(long)]
+    pub verify: bool,
+    /// Like --verify, then download the missing files
+    #[clap(long)]
+    pub repair: bool,
+    /// Print where the configuration, tokens and downloads are kept
+    #[clap(long)]
+    pub paths: bool,
+    /// Print the size and number of files of each album folder, the largest first
+    #[clap(long)]
+    pub du: bool,
+    /// Write the configuration to a file, to import it on another machine
+    #[clap(long, value_name = "FILE")]
+    pub export_config: Option<PathBuf>,
+    /// Replace the configuration with one written by --export-config
+    #[clap(long, value_name = "FILE")]
+    pub import_config: Option<PathBuf>,
+    /// Remove the configuration, tokens and caches, and optionally the downloaded media
+    #[clap(long)]
+    pub purge: bool,
+    /// Print the private and shared albums, with their id and web link
+    #[clap(long)]
+    pub list_albums: bool,
+    /// Print the library items matching the filters instead of downloading them
+    #[clap(long)]
+    pub search: bool,
+    /// Only fetch the items created since the day of the last complete synchronization
+    #[clap(long)]
+    pub since_last_run: bool,
+    /// Write a JSON sidecar with the Google metadata next to each downloaded file
+    #[clap(long)]
+    pub write_metadata: bool,
+    /// Write an index.html and a manifest.m3u playlist of the media into each folder
+    #[clap(long)]
+    pub write_index: bool,
+    /// Write a .nomedia file into each folder, hiding it from the Android galleries
+    #[clap(long)]
+    pub write_nomedia: bool,
+    /// Include archived items when searching the library
+    #[clap(long)]
+    pub include_archived: bool,
+    /// Only search for items marked as favorite
+    #[clap(long)]
+    pub favorites_only: bool,
+    /// Only search for items of this category, can be repeated
+    #[clap(long = "category", arg_enum, ignore_case = true)]
+    pub categories: Vec<ContentCategory>,
+    /// Only search for items created on or after this date, as YYYY-MM-DD
+    #[clap(long)]
+    pub from: Option<NaiveDate>,
+    /// Only search for items created on or before this date, as YYYY-MM-DD
+    #[clap(long)]
+    pub to: Option<NaiveDate>,
+    /// Fetch the library items in this order of creation
+    #[clap(long, arg_enum)]
+    pub order: Option<Order>,
+    /// Download the newest items first: the library by creation, the albums page by page. Disables resuming the albums
+    #[clap(long, conflicts_with_all = &["order", "sequence-prefix"])]
+    pub newest_first: bool,
+    /// Skip the files with this extension, like gif, can be repeated
+    #[clap(long = "exclude-ext", value_name = "EXT")]
+    pub excluded_extensions: Vec<String>,
+    /// Skip the files bigger than this size, like 500MB or 2G
+    #[clap(long, value_name = "SIZE", parse(try_from_str = parse_size))]
+    pub max_size: Option<u64>,
+    /// Skip the items smaller than this resolution, like 1920x1080, in either orientation
+    #[clap(long, value_name = "WIDTHxHEIGHT", parse(try_from_str = parse_resolution))]
+    pub min_resolution: Option<Resolution>,
+    /// Sort the items into subfolders of their album by creation date, like {year}/{month}
+    #[clap(long, value_name = "TEMPLATE")]
+    pub folder_template: Option<String>,
+    /// Prefix the file names with their position in the album, like 0001_, to keep its order
+    #[clap(long)]
+    pub sequence_prefix: bool,
+    /// Download the cover of each album into its folder, as _cover.jpg
+    #[clap(long)]
+    pub download_cover: bool,
+    /// Only download the items at these positions in the listing, like 100-200, counting from 1.
+    /// The last synchronization is left as it was
+    #[clap(
+        long,
+        value_name = "START-END",
+        parse(try_from_str = parse_range),
+        conflicts_with = "download-missing-only"
+    )]
+    pub range: Option<RangeInclusive<usize>>,
+    /// Stop each album after downloading this many items, to try things out
+    #[clap(long, value_name = "N")]
+    pub max_items: Option<usize>,
+    /// Convert the HEIC photos to JPEG, losing their EXIF, for the devices that can't show them
+    #[clap(long)]
+    pub convert_heic: bool,
+    /// With --convert-heic, keep the HEIC photos next to the JPEG ones
+    #[clap(long, requires = "convert-heic")]
+    pub keep_heic: bool,
+    /// Also download the motion of Live Photos and motion photos, as a video next to the photo
+    #[clap(long)]
+    pub live_photos: bool,
+    /// Try the downloads failing on a network error again, up to this many times, waiting longer each time
+    #[clap(long, value_name = "N", default_value = "3")]
+    pub max_retries: u32,
+    /// What to do with the items that are neither photos nor videos
+    #[clap(long, arg_enum, default_value = "download")]
+    pub unknown_media: UnknownMedia,
+    /// Give up on the items taking longer than this to download, like 90s or 5m
+    #[clap(long, value_name = "DURATION", parse(try_from_str = parse_duration))]
+    pub timeout_per_item: Option<Duration>,
+    /// Stop starting downloads after this long, like 30m or 2h, finishing the current ones
+    #[clap(long, value_name = "DURATION", parse(try_from_str = parse_duration))]
+    pub deadline: Option<Duration>,
+    /// Download the photos again when their dimensions differ from Google's
+    #[clap(long)]
+    pub verify_dimensions: bool,
+    /// Print the completion script for the given shell
+    #[clap(long, arg_enum, value_name = "SHELL")]
+    pub generate_completions: Option<Shell>,
+    /// Print the man page, in roff
+    #[clap(long)]
+    pub generate_man: bool,
+}
+
+impl Cli {
+    /// The chosen theme, without colors if they are disabled or unsupported, following NO_COLOR.
+    #[cfg(feature = "interactive")]
+    pub fn theme(&self) -> ThemeChoice {
+        if self.no_color || !console::colors_enabled_stderr() {
+            ThemeChoice::Simple
+        } else {
+            self.theme
+        }
+    }
+
+    /// The album picked on the command line, if any.
+    pub fn album_choice(&self) -> Option<AlbumChoice> {
+        match (self.album_index, &self.album_title) {
+            (Some(index), _) => Some(AlbumChoice::Index(index)),
+            (None, Some(title)) => Some(AlbumChoice::Title(title.clone())),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Parses a size in bytes, with an optional decimal unit like `K`, `MB` or `G`.
+fn parse_size(size: &str) -> Result<u64, String> {
+    let error = || format!("{size} isn't a valid size");
+    let normalized = size.trim().to_uppercase();
+    let normalized = normalized.strip_suffix('B').unwrap_or(&normalized);
+    let (number, multiplier) = match normalized.char_indices().last() {
+        Some((index, 'K')) => (&normalized[..index], 1_000),
+        Some((index, 'M')) => (&normalized[..index], 1_000_000),
+        Some((index, 'G')) => (&normalized[..index], 1_000_000_000),
+        _ => (normalized, 1),
+    };
+
+    let number: u64 = number.trim().parse().map_err(|_| error())?;
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("{size} is too large"))
+}
+
+/// Parses a duration in seconds, with an optional unit like `s`, `m` or `h`.
+fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let error = || format!("{duration} isn't a valid duration, like 90s, 30m or 2h");
+    let normalized = duration.trim().to_lowercase();
+    let (number, multiplier) = match normalized.char_indices().last() {
+        Some((index, 's')) => (&normalized[..index], 1),
+        Some((index, 'm')) => (&normalized[..index], 60),
+        Some((index, 'h')) => (&normalized[..index], 3600),
+        _ => (normalized.as_str(), 1),
+    };
+
+    let number: u64 = number.trim().parse().map_err(|_| error())?;
+    number
+        .checked_mul(multiplier)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("{duration} is too long"))
+}
+
+/// Parses a range of positions like `100-200`, counting from 1, both ends included.
+fn parse_range(range: &str) -> Result<RangeInclusive<usize>, String> {
+    let error = || format!("{range} isn't a valid range, like 100-200");
+    let (start, end) = range.trim().split_once('-').ok_or_else(error)?;
+    let (start, end): (usize, usize) = match (start.trim().parse(), end.trim().parse()) {
+        (Ok(start), Ok(end)) => (start, end),
+        _ => return Err(error()),
+    };
+
+    if start == 0 {
+        return Err("positions count from 1".to_string());
+    }
+    if start > end {
+        return Err(format!("{range} ends before it starts"));
+    }
+
+    Ok(start..=end)
+}
+
+/// Parses a resolution like `1920x1080`.
+fn parse_resolution(resolution: &str) -> Result<Resolution, String> {
+    let error = || format!("{resolution} isn't a valid resolution, like 1920x1080");
+    let (width, height) = resolution
+        .trim()
+        .to_lowercase()
+        .split_once('x')
+        .map(|(width, height)| (width.trim().parse(), height.trim().parse()))
+        .ok_or_else(error)?;
+
+    match (width, height) {
+        (Ok(width), Ok(height)) => Ok(Resolution { width, height }),
+        _ => Err(error()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes_with_their_unit() {
+        assert_eq!(parse_size("500"), Ok(500));
+        assert_eq!(parse_size("500B"), Ok(500));
+        assert_eq!(parse_size("2k"), Ok(2_000));
+        assert_eq!(parse_size("20MB"), Ok(20_000_000));
+        assert_eq!(parse_size("3G"), Ok(3_000_000_000));
+    }
+
+    #[test]
+    fn parses_sizes_around_whitespace() {
+        assert_eq!(parse_size(" 20 MB "), Ok(20_000_000));
+    }
+
+    #[test]
+    fn parses_a_zero_size() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("0G"), Ok(0));
+    }
+
+    #[test]
+    fn rejects_a_size_overflowing() {
+        assert_eq!(
+            parse_size("20000000000G"),
+            Err("20000000000G is too large".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_a_malformed_size_as_written() {
+        assert_eq!(
+            parse_size("500xb"),
+            Err("500xb isn't a valid size".to_string())
+        );
+        assert!(parse_size("").is_err());
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("-5K").is_err());
+    }
+
+    #[test]
+    fn parses_durations_with_their_unit() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration(" 30 M "), Ok(Duration::from_secs(1_800)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7_200)));
+        assert_eq!(parse_duration("0m"), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn rejects_a_duration_overflowing() {
+        let duration = format!("{}h", u64::MAX);
+
+        assert_eq!(
+            parse_duration(&duration),
+            Err(format!("{duration} is too long"))
+        );
+    }
+
+    #[test]
+    fn rejects_a_malformed_duration_as_written() {
+        assert_eq!(
+            parse_duration("2 days"),
+            Err("2 days isn't a valid duration, like 90s, 30m or 2h".to_string())
+        );
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("h").is_err());
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_range("100-200"), Ok(100..=200));
+        assert_eq!(parse_range(" 5 - 5 "), Ok(5..=5));
+    }
+
+    #[test]
+    fn rejects_malformed_ranges() {
+        assert_eq!(
+            parse_range("0-10"),
+            Err("positions count from 1".to_string())
+        );
+        assert_eq!(
+            parse_range("200-100"),
+            Err("200-100 ends before it starts".to_string())
+        );
+        for range in ["", "100", "100-", "a-b", "1-2-3"] {
+            assert!(parse_range(range).is_err(), "{range}");
+        }
+    }
+
+    #[test]
+    fn parses_resolutions() {
+        let resolution = parse_resolution(" 1920 X 1080 ").unwrap();
+
+        assert_eq!((resolution.width, resolution.height), (1920, 1080));
+    }
+
+    #[test]
+    fn rejects_malformed_resolutions() {
+        for resolution in ["", "1920", "1920x", "x1080", "1920*1080", "-1x5"] {
+            assert!(parse_resolution(resolution).is_err(), "{resolution}");
+        }
+    }
 }
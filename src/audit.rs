@@ -0,0 +1,81 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use directories::ProjectDirs;
+use serde::Serialize;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::{
+    item::{Item, Outcome},
+    output::warning,
+};
+
+pub const AUDIT_FILE: &str = "audit.jsonl";
+
+/// A record of every download, one JSON line each, kept across runs in the data folder.
+///
+/// Unlike the manifests, it is never rewritten: it tells what was mirrored when.
+pub struct AuditLog {
+    file: Mutex<File>,
+}
+
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+    timestamp: DateTime<Utc>,
+    id: &'a str,
+    filename: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    destination: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes: Option<u64>,
+    outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl AuditLog {
+    pub fn open(project_dirs: &ProjectDirs) -> Result<Self> {
+        fs::create_dir_all(project_dirs.data_dir())?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(project_dirs.data_dir().join(AUDIT_FILE))?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Records what came of downloading `item` into `folder`. Failing to record it is only worth
+    /// a warning, the download itself went through.
+    pub fn record(&self, item: &Item, folder: &Path, outcome: &Result<Outcome>) {
+        let (destination, outcome, error) = match outcome {
+            Ok(Outcome::Downloaded { file, .. }) => (Some(folder.join(file)), "downloaded", None),
+            Ok(Outcome::Skipped) => (None, "skipped", None),
+            Err(e) => (None, "failed", Some(format!("{e:#}"))),
+        };
+        let entry = AuditEntry {
+            timestamp: Utc::now(),
+            id: item.id().as_str(),
+            filename: item.filename(),
+            bytes: destination
+                .as_ref()
+                .and_then(|destination| fs::metadata(destination).ok())
+                .map(|metadata| metadata.len()),
+            destination,
+            outcome,
+            error,
+        };
+
+        let result = serde_json::to_string(&entry)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| Ok(writeln!(self.file.lock().unwrap(), "{line}")?));
+        if let Err(e) = result {
+            warning!("couldn't write to the audit log: {e}");
+        }
+    }
+}
@@ -1,13 +1,73 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use async_once::AsyncOnce;
+use async_trait::async_trait;
 use directories::ProjectDirs;
 use lazy_static::lazy_static;
-use reqwest::{
-    header::{HeaderMap, HeaderValue, AUTHORIZATION},
-    Client,
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock,
+    },
 };
+use yup_oauth2::{
+    authenticator::DefaultAuthenticator,
+    storage::{TokenInfo, TokenStorage},
+    AccessToken, ApplicationSecret,
+};
+
+use crate::{
+    api::{AlbumsListRequest, AlbumsListResponse, Api, SCOPES},
+    output::warning,
+};
+
+pub const TOKEN_CACHE_FILE: &str = "tokencache.json";
+const DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+static USER_AGENT: OnceLock<String> = OnceLock::new();
+
+/// Overrides the User-Agent sent to Google, before the first request.
+pub fn set_user_agent(user_agent: String) {
+    let _ = USER_AGENT.set(user_agent);
+}
+
+static CREDENTIALS: OnceLock<PathBuf> = OnceLock::new();
+
+/// Signs in with the credentials in this file rather than through the browser, before the first
+/// request, see [`headless_authenticator`].
+pub fn set_credentials(path: PathBuf) {
+    let _ = CREDENTIALS.set(path);
+}
+
+static FORCE_REAUTH: AtomicBool = AtomicBool::new(false);
+
+/// Signs in through the browser again, before the first request, dropping the cached tokens.
+pub fn set_force_reauth() {
+    FORCE_REAUTH.store(true, Ordering::Relaxed);
+}
+
+static HTTP_CLIENT: OnceLock<Client> = OnceLock::new();
+
+/// The HTTP client of every request, to Google's API and to the media it serves alike, so that
+/// they all share the User-Agent and the connections.
+pub fn http_client() -> reqwest::Result<&'static Client> {
+    if let Some(client) = HTTP_CLIENT.get() {
+        return Ok(client);
+    }
 
-use crate::api::Api;
+    // Sends the matching Accept-Encoding, the listings compress well.
+    let client = Client::builder()
+        .user_agent(USER_AGENT.get().map_or(DEFAULT_USER_AGENT, String::as_str))
+        .gzip(true)
+        .brotli(true)
+        .build()?;
+
+    Ok(HTTP_CLIENT.get_or_init(|| client))
+}
 
 lazy_static! {
     static ref CLIENT: AsyncOnce<Result<Api>> = AsyncOnce::new(async { init_api().await });
@@ -18,11 +78,24 @@ pub async fn get_api<'a>() -> Result<&'a Api> {
         .get()
         .await
         .as_ref()
-        .map_err(|_| anyhow!("Error getting the client"));
+        .map_err(|e| anyhow!("Error getting the client: {e:#}"));
 
     client
 }
 
+/// Signs in if needed, then makes the smallest authenticated request there is, so that
+/// authentication problems can be told apart from synchronization ones.
+pub async fn check_auth() -> Result<()> {
+    let api = get_api().await?;
+    let mut request = AlbumsListRequest::default();
+    request.page_size = Some(1);
+    let _: AlbumsListResponse = api
+        .get("https://photoslibrary.googleapis.com/v1/albums", &request)
+        .await?;
+
+    Ok(())
+}
+
 async fn init_api() -> Result<Api> {
     let project_dirs = ProjectDirs::from("app", "Redwarp", "Sync Google Photo")
         .expect("Couldn't create a project dir");
@@ -32,26 +105,127 @@ async fn init_api() -> Result<Api> {
     let secret = yup_oauth2::parse_application_secret(include_bytes!("client_secrets.json"))
         .expect("Should be valid");
 
-    let auth = yup_oauth2::InstalledFlowAuthenticator::builder(
+    // Signs in right away, rather than in the middle of the first listing.
+    let (auth, token) = match CREDENTIALS.get() {
+        Some(path) => headless_authenticator(path, secret)
+            .await
+            .with_context(|| format!("Couldn't use the credentials in {}", path.display()))?,
+        None => {
+            let token_cache = config_dir.join(TOKEN_CACHE_FILE);
+            if FORCE_REAUTH.load(Ordering::Relaxed) && token_cache.exists() {
+                std::fs::remove_file(&token_cache)?;
+            }
+            let auth = match installed_flow_authenticator(secret.clone(), &token_cache).await {
+                // An unreadable cache, as after a partial write, is only worth signing in again.
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    warning!(
+                        "the token cache {} is corrupt ({e}), signing in again",
+                        token_cache.display()
+                    );
+                    std::fs::remove_file(&token_cache)?;
+                    installed_flow_authenticator(secret, &token_cache).await?
+                }
+                auth => auth?,
+            };
+            let token = auth.token(SCOPES).await?;
+            (auth, token)
+        }
+    };
+
+    let api = Api::new(http_client()?.clone(), auth, token);
+
+    Ok(api)
+}
+
+/// Signs in through the browser, keeping the tokens in `token_cache`.
+async fn installed_flow_authenticator(
+    secret: ApplicationSecret,
+    token_cache: &Path,
+) -> io::Result<DefaultAuthenticator> {
+    yup_oauth2::InstalledFlowAuthenticator::builder(
         secret,
         yup_oauth2::InstalledFlowReturnMethod::HTTPRedirect,
     )
-    .persist_tokens_to_disk(config_dir.join("tokencache.json"))
+    .persist_tokens_to_disk(token_cache)
     .build()
-    .await?;
+    .await
+}
 
-    let scopes = &["https://www.googleapis.com/auth/photoslibrary.readonly"];
+/// A refresh token obtained elsewhere, in the format of
+/// `gcloud auth application-default login`. Without a client, it must have been issued to this
+/// application.
+#[derive(Deserialize)]
+struct AuthorizedUser {
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    refresh_token: String,
+}
 
-    let token = auth.token(scopes).await?;
+/// Signs in without a browser, for servers, with either a refresh token or a service account key.
+///
+/// Google Photos only shows a service account its own library, empty unless something was
+/// uploaded with it: a refresh token is what gives access to someone's photos.
+async fn headless_authenticator(
+    path: &Path,
+    mut secret: ApplicationSecret,
+) -> Result<(DefaultAuthenticator, AccessToken)> {
+    let contents = std::fs::read(path)?;
+    let credentials: Value = serde_json::from_slice(&contents)?;
 
-    let mut headers = HeaderMap::new();
-    let mut auth_value: HeaderValue = format!("Bearer {}", token.as_str()).parse()?;
-    auth_value.set_sensitive(true);
+    match credentials["type"].as_str() {
+        Some("authorized_user") => {
+            let user: AuthorizedUser = serde_json::from_value(credentials)?;
+            if let (Some(client_id), Some(client_secret)) = (user.client_id, user.client_secret) {
+                secret.client_id = client_id;
+                secret.client_secret = client_secret;
+            }
+            let storage = MemoryStorage(Mutex::new(TokenInfo {
+                access_token: String::new(),
+                refresh_token: Some(user.refresh_token),
+                expires_at: None,
+                id_token: None,
+            }));
+            // Never opened, the stored refresh token is used instead.
+            let auth = yup_oauth2::InstalledFlowAuthenticator::builder(
+                secret,
+                yup_oauth2::InstalledFlowReturnMethod::Interactive,
+            )
+            .with_storage(Box::new(storage))
+            .build()
+            .await?;
+            // The stored token has no access token yet, only a refresh one.
+            let token = auth.force_refreshed_token(SCOPES).await?;
+            Ok((auth, token))
+        }
+        Some("service_account") => {
+            let key = yup_oauth2::parse_service_account_key(&contents)?;
+            let auth = yup_oauth2::ServiceAccountAuthenticator::builder(key)
+                .build()
+                .await?;
+            let token = auth.token(SCOPES).await?;
+            Ok((auth, token))
+        }
+        _ => bail!("Expected an authorized_user or a service_account credentials file"),
+    }
+}
 
-    headers.insert(AUTHORIZATION, auth_value);
+/// Keeps the token in memory only, the refresh token staying in the credentials file.
+struct MemoryStorage(Mutex<TokenInfo>);
 
-    let client = Client::builder().default_headers(headers).build()?;
-    let api = Api::new(client);
+#[async_trait]
+impl TokenStorage for MemoryStorage {
+    async fn set(&self, _scopes: &[&str], token: TokenInfo) -> anyhow::Result<()> {
+        let mut stored = self.0.lock().unwrap();
+        // Refreshing doesn't always give a new refresh token.
+        let refresh_token = token.refresh_token.or_else(|| stored.refresh_token.take());
+        *stored = TokenInfo {
+            refresh_token,
+            ..token
+        };
+        Ok(())
+    }
 
-    Ok(api)
+    async fn get(&self, _scopes: &[&str]) -> Option<TokenInfo> {
+        Some(self.0.lock().unwrap().clone())
+    }
 }
@@ -1,106 +1,844 @@
-use anyhow::Result;
-use dialoguer::{theme::ColorfulTheme, Select};
-use directories::ProjectDirs;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, NaiveDate, Utc};
+#[cfg(feature = "interactive")]
+use dialoguer::{theme::Theme, Confirm, Input};
+use directories::{ProjectDirs, UserDirs};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::{
-    fs::{create_dir_all, remove_file, File},
-    path::PathBuf,
-    str::FromStr,
+    fmt::Display,
+    fs::{copy, create_dir_all, read_dir, rename, File},
+    path::{Path, PathBuf},
 };
 
-use crate::{album::pick_album, api::Id, client::get_api};
+use crate::{
+    album::find_album,
+    api::{Album, ContentCategory, Id},
+    args::Cli,
+    audit::AUDIT_FILE,
+    client::{get_api, TOKEN_CACHE_FILE},
+    item::{sanitize_file_name, MediaType, Quality},
+    output::{info, warning},
+    progress::Tally,
+};
+#[cfg(feature = "interactive")]
+use crate::{album::pick_album, menu, theme::ensure_interactive};
 
 const CONFIG_FILE: &str = "config.json";
-const MANIFEST_DIR: &str = env!("CARGO_MANIFEST_DIR");
+/// The configuration before it was last imported or configured, see [`backup`].
+const BACKUP_FILE: &str = "config.json.bak";
 
 #[derive(Serialize, Deserialize)]
 pub struct LocalAlbum {
+    /// Relative to the downloads root, see [`Configuration::album_path`].
     pub path: PathBuf,
     pub album_id: Id,
     pub name: String,
+    /// When the last complete synchronization started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_sync: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_status: Option<SyncStatus>,
+    /// The page an unfinished synchronization stopped at, to resume from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resume_page_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filters: Option<AlbumFilters>,
+    /// How large the photos are downloaded, originals by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<Quality>,
+}
+
+/// Filters that only apply to one album, on top of the command line ones, set by editing the
+/// configuration file.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct AlbumFilters {
+    /// Only the items created on or after this day.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<NaiveDate>,
+    /// Only the items created on or before this day.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<NaiveDate>,
+    /// Only the photos, or the videos.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<MediaType>,
+    /// Only the items in one of these categories, like `"PEOPLE"`.
+    ///
+    /// Google can't filter an album by category, so each synchronization also searches the
+    /// library for these categories between `from` and `to`, once for all the albums sharing them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<ContentCategory>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncOutcome {
+    Succeeded,
+    Interrupted,
+    /// Stopped at `--max-items`, the rest of the items are still to download.
+    Incomplete,
+    Failed,
+}
+
+/// How the last synchronization of an album went.
+#[derive(Serialize, Deserialize)]
+pub struct SyncStatus {
+    pub started_at: DateTime<Utc>,
+    pub downloaded: usize,
+    pub outcome: SyncOutcome,
+}
+
+impl SyncStatus {
+    /// `max_items` is the most items the synchronization could download, a run reaching it may
+    /// have left some behind.
+    pub fn new(
+        started_at: DateTime<Utc>,
+        result: &Result<Tally>,
+        interrupted: bool,
+        max_items: Option<usize>,
+    ) -> Self {
+        let is_capped = |tally: &Tally| max_items.is_some_and(|max| tally.downloaded >= max);
+        let (downloaded, outcome) = match result {
+            // Some items are missing, the next synchronization must not skip them.
+            Ok(tally) if tally.failed > 0 => (tally.downloaded, SyncOutcome::Failed),
+            Ok(tally) if interrupted => (tally.downloaded, SyncOutcome::Interrupted),
+            Ok(tally) if is_capped(tally) => (tally.downloaded, SyncOutcome::Incomplete),
+            Ok(tally) => (tally.downloaded, SyncOutcome::Succeeded),
+            Err(_) => (0, SyncOutcome::Failed),
+        };
+
+        Self {
+            started_at,
+            downloaded,
+            outcome,
+        }
+    }
+}
+
+impl Display for SyncStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let started_at = self
+            .started_at
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M");
+        match self.outcome {
+            SyncOutcome::Succeeded => write!(
+                f,
+                "synchronized on {started_at}, {} items downloaded",
+                self.downloaded
+            ),
+            SyncOutcome::Interrupted => write!(
+                f,
+                "interrupted on {started_at}, {} items downloaded",
+                self.downloaded
+            ),
+            SyncOutcome::Incomplete => write!(
+                f,
+                "stopped at the maximum on {started_at}, {} items downloaded",
+                self.downloaded
+            ),
+            SyncOutcome::Failed => write!(
+                f,
+                "failed on {started_at}, {} items downloaded",
+                self.downloaded
+            ),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Configuration {
     pub local_albums: Vec<LocalAlbum>,
+    /// Where the albums and the library go, see [`Configuration::downloads_root`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub downloads_root: Option<PathBuf>,
+    /// Where the whole library goes when synchronized, see [`Configuration::library_path`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub library_path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub library_last_sync: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub library_status: Option<SyncStatus>,
 }
 
 impl Configuration {
-    fn save(&self, project_dirs: &ProjectDirs) -> Result<()> {
+    pub fn save(&self, project_dirs: &ProjectDirs) -> Result<()> {
         create_dir_all(project_dirs.config_dir())?;
 
+        // Written aside then renamed, for a failed write not to leave a truncated configuration.
         let config_file = project_dirs.config_dir().join(CONFIG_FILE);
-        if config_file.exists() {
-            remove_file(&config_file)?;
-        }
-        serde_json::to_writer(&File::create(config_file)?, self)?;
+        let temp_file = config_file.with_extension("json.tmp");
+        serde_json::to_writer(&File::create(&temp_file)?, self)?;
+        rename(&temp_file, config_file)?;
 
         Ok(())
     }
 
+    /// Loads the configuration, skipping the malformed albums and fields, as with a typo in a
+    /// hand-edited file, rather than losing access to all of them.
+    ///
+    /// The original file is backed up when albums are skipped, as they will be gone once saved.
     pub fn load(project_dirs: &ProjectDirs) -> Result<Self> {
         let config_file = project_dirs.config_dir().join(CONFIG_FILE);
         if config_file.exists() {
-            let configuration: Configuration = serde_json::from_reader(&File::open(&config_file)?)?;
+            let (configuration, skipped) = Self::read(&config_file)?;
+            if skipped > 0 {
+                // Not the backup of the saves, which the next one replaces.
+                let backup_file = config_file.with_extension("json.orig");
+                copy(&config_file, &backup_file)?;
+                warning!(
+                    "the original configuration is kept in {}",
+                    backup_file.display()
+                );
+            }
 
             Ok(configuration)
         } else {
             Ok(Configuration {
                 local_albums: vec![],
+                // Saved, not to be mistaken for a configuration older than the setting.
+                downloads_root: Some(default_downloads_root()),
+                library_path: None,
+                library_last_sync: None,
+                library_status: None,
             })
         }
     }
 
+    /// Reads the configuration saved in `file`, returning it along with how many malformed albums
+    /// were skipped. Older configurations get the default downloads folder.
+    fn read(file: &Path) -> Result<(Self, usize)> {
+        let value: Value = serde_json::from_reader(&File::open(file)?)
+            .with_context(|| format!("{} isn't valid JSON", file.display()))?;
+        Self::from_value(value)
+            .with_context(|| format!("{} isn't a valid configuration", file.display()))
+    }
+
+    fn from_value(mut value: Value) -> Result<(Self, usize)> {
+        if !value.is_object() {
+            bail!("Not a JSON object");
+        }
+
+        let albums = match value.get_mut("local_albums").map(Value::take) {
+            Some(Value::Array(albums)) => albums,
+            _ => vec![],
+        };
+        let album_count = albums.len();
+        let local_albums: Vec<LocalAlbum> = albums
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, album)| match serde_json::from_value(album) {
+                Ok(local_album) => Some(local_album),
+                Err(e) => {
+                    warning!(
+                        "skipping the album #{} of the configuration: {e}",
+                        index + 1
+                    );
+                    None
+                }
+            })
+            .collect();
+
+        value["local_albums"] = Value::Array(vec![]);
+        // The other fields are dropped one by one too, rather than the whole configuration.
+        let fields = value.as_object_mut().expect("Checked above");
+        fields.retain(|key, field| {
+            let mut single = serde_json::Map::new();
+            single.insert("local_albums".to_string(), Value::Array(vec![]));
+            single.insert(key.clone(), field.clone());
+            match serde_json::from_value::<Configuration>(Value::Object(single)) {
+                Ok(_) => true,
+                Err(e) => {
+                    warning!("skipping the {key} of the configuration: {e}");
+                    false
+                }
+            }
+        });
+        let mut configuration: Configuration = serde_json::from_value(value)?;
+        if configuration.downloads_root.is_none() {
+            // Older albums have absolute paths, they stay where they are. Saved, to be told only
+            // once.
+            let downloads_root = default_downloads_root();
+            warning!(
+                "the configuration has no downloads folder, the library and the new albums go to {}",
+                downloads_root.display()
+            );
+            configuration.downloads_root = Some(downloads_root);
+        }
+
+        let skipped = album_count - local_albums.len();
+        Ok((
+            Configuration {
+                local_albums,
+                ..configuration
+            },
+            skipped,
+        ))
+    }
+
+    /// The folder everything is downloaded into, see [`default_downloads_root`] unless
+    /// configured.
+    pub fn downloads_root(&self) -> PathBuf {
+        match &self.downloads_root {
+            Some(downloads_root) => downloads_root.clone(),
+            None => default_downloads_root(),
+        }
+    }
+
+    /// The folder of an album. Absolute paths, from older configurations, are kept as they are.
+    pub fn album_path(&self, local_album: &LocalAlbum) -> PathBuf {
+        self.downloads_root().join(&local_album.path)
+    }
+
+    /// The folder the whole library is synchronized into, next to the albums unless configured.
+    pub fn library_path(&self) -> PathBuf {
+        match &self.library_path {
+            Some(path) => self.downloads_root().join(path),
+            None => self.downloads_root().join("Library"),
+        }
+    }
+
+    /// The index of the album with this id, added to the configuration if it isn't there yet.
+    ///
+    /// An added album is named after its id, as it is not known yet.
+    pub fn album_index(&mut self, album_id: &str) -> Result<usize> {
+        if let Some(index) = self
+            .local_albums
+            .iter()
+            .position(|local_album| *local_album.album_id == album_id)
+        {
+            return Ok(index);
+        }
+
+        self.local_albums.push(LocalAlbum {
+            path: PathBuf::from(sanitize_file_name(album_id)),
+            album_id: Id(album_id.to_string()),
+            name: album_id.to_string(),
+            last_sync: None,
+            last_status: None,
+            resume_page_token: None,
+            filters: None,
+            quality: None,
+        });
+
+        Ok(self.local_albums.len() - 1)
+    }
+
+    /// Adds an album to synchronize, in a folder named after its title.
+    ///
+    /// With `dry_run`, only tells where it would go, for the caller not to save anything.
+    fn push_album(&mut self, album: Album, dry_run: bool) {
+        let local_album = LocalAlbum {
+            path: PathBuf::from(sanitize_file_name(&album.title)),
+            album_id: album.id,
+            name: album.title.trim().to_string(),
+            last_sync: None,
+            last_status: None,
+            resume_page_token: None,
+            filters: None,
+            quality: None,
+        };
+
+        if dry_run {
+            println!(
+                "Would synchronize {} into {}",
+                local_album.name,
+                self.album_path(&local_album).display()
+            );
+        } else {
+            self.local_albums.push(local_album);
+        }
+    }
+
+    #[cfg(feature = "interactive")]
+    fn print_library(&self) -> Result<()> {
+        println!("Library folder: {}", self.library_path().display());
+        match &self.library_status {
+            Some(status) => println!("Library: {status}"),
+            None => println!("Library: never synchronized"),
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "interactive")]
     fn list_albums(&self) {
         if self.local_albums.is_empty() {
             println!("No album yet");
         }
 
         for local_album in &self.local_albums {
-            println!("{}", local_album.name);
+            match &local_album.last_status {
+                Some(status) => println!("{}: {status}", local_album.name),
+                None => println!("{}: never synchronized", local_album.name),
+            }
         }
     }
 }
 
-pub async fn configure(project_dirs: &ProjectDirs) -> Result<()> {
-    let choices = vec!["List synchronized albums", "Synchronize new album"];
+/// The configuration menu, its submenus coming back to it until the user quits.
+#[cfg(feature = "interactive")]
+pub async fn configure(project_dirs: &ProjectDirs, cli: &Cli) -> Result<()> {
+    ensure_interactive()?;
+    let theme = cli.theme().theme();
+    let theme = theme.as_ref();
+
     let mut configuration = Configuration::load(project_dirs)?;
+    // Once per session, for the backup to be the configuration from before it.
+    if let Some(backup_file) = backup(project_dirs)? {
+        info!(
+            "The current configuration is kept in {}",
+            backup_file.display()
+        );
+    }
 
-    let selection = Select::with_theme(&ColorfulTheme::default())
-        .items(&choices)
-        .default(0)
-        .interact()?;
-    match selection {
-        0 => configuration.list_albums(),
-        1 => {
-            add_new_album(&mut configuration, project_dirs).await?;
+    let choices = [
+        "Albums",
+        "Library",
+        "Downloads folder",
+        "Synchronize all albums now",
+    ];
+    while let Some(choice) =
+        menu::select_with_exit(theme, "What do you want to configure?", &choices, "Quit")?
+    {
+        match choice {
+            0 => configure_albums(&mut configuration, project_dirs, theme, cli).await?,
+            1 => configure_library(&mut configuration, project_dirs, theme)?,
+            2 => {
+                // The files already downloaded are left in place, to be moved by hand.
+                let path: String = Input::with_theme(theme)
+                    .with_prompt("Downloads folder")
+                    .with_initial_text(configuration.downloads_root().display().to_string())
+                    .interact_text()?;
+                configuration.downloads_root = Some(PathBuf::from(path.trim()));
+                configuration.save(project_dirs)?;
+            }
+            3 => {
+                // Back to the menu whatever happens.
+                if let Err(e) = crate::synchronize(project_dirs, cli).await {
+                    eprintln!("Error: {e:#}");
+                }
+                // Picks up the status of the albums.
+                configuration = Configuration::load(project_dirs)?;
+            }
+            _ => unreachable!("Only four choices in the menu"),
         }
-        _ => unreachable!("Only two choices in the menu"),
-    };
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "interactive")]
+async fn configure_albums(
+    configuration: &mut Configuration,
+    project_dirs: &ProjectDirs,
+    theme: &dyn Theme,
+    cli: &Cli,
+) -> Result<()> {
+    let choices = [
+        "List synchronized albums",
+        "Synchronize new album",
+        "Rename an album",
+    ];
+    while let Some(choice) = menu::select_with_exit(theme, "Albums", &choices, "Back")? {
+        match choice {
+            0 => configuration.list_albums(),
+            1 => add_new_album(configuration, project_dirs, theme, cli).await?,
+            2 => rename_album(configuration, project_dirs, theme)?,
+            _ => unreachable!("Only three choices in the menu"),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "interactive")]
+fn configure_library(
+    configuration: &mut Configuration,
+    project_dirs: &ProjectDirs,
+    theme: &dyn Theme,
+) -> Result<()> {
+    let choices = ["Show the library", "Change the library folder"];
+    while let Some(choice) = menu::select_with_exit(theme, "Library", &choices, "Back")? {
+        match choice {
+            0 => configuration.print_library()?,
+            1 => {
+                let path: String = Input::with_theme(theme)
+                    .with_prompt("Library folder")
+                    .with_initial_text(configuration.library_path().display().to_string())
+                    .interact_text()?;
+                configuration.library_path = Some(PathBuf::from(path.trim()));
+                configuration.save(project_dirs)?;
+            }
+            _ => unreachable!("Only two choices in the menu"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints where the tool keeps its files. On Linux, they follow `XDG_CONFIG_HOME` and friends.
+pub fn print_paths(project_dirs: &ProjectDirs) -> Result<()> {
+    let config_dir = project_dirs.config_dir();
+    println!("Config dir:  {}", config_dir.display());
+    println!("Data dir:    {}", project_dirs.data_dir().display());
+    println!("Cache dir:   {}", project_dirs.cache_dir().display());
+    println!("Config file: {}", config_dir.join(CONFIG_FILE).display());
+    println!(
+        "Token cache: {}",
+        config_dir.join(TOKEN_CACHE_FILE).display()
+    );
+    println!(
+        "Audit log:   {}",
+        project_dirs.data_dir().join(AUDIT_FILE).display()
+    );
+    println!(
+        "Downloads:   {}",
+        Configuration::load(project_dirs)?
+            .downloads_root()
+            .display()
+    );
+
+    Ok(())
+}
+
+/// Prints the size and number of files of each album folder, the largest first, then of the
+/// library if it was synchronized.
+pub fn print_disk_usage(project_dirs: &ProjectDirs) -> Result<()> {
+    let configuration = Configuration::load(project_dirs)?;
+    let mut usages = configuration
+        .local_albums
+        .iter()
+        .map(|local_album| {
+            let usage = disk_usage(&configuration.album_path(local_album))?;
+            Ok((local_album.name.as_str(), usage))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    usages.sort_by_key(|&(_, (bytes, _))| std::cmp::Reverse(bytes));
+    let library_path = configuration.library_path();
+    if library_path.exists() {
+        usages.push(("Library", disk_usage(&library_path)?));
+    }
+
+    let megabytes = |bytes| bytes as f64 / 1_000_000.0;
+    for (name, (bytes, files)) in &usages {
+        println!("{name}: {:.1} MB, {files} files", megabytes(*bytes));
+    }
+    let (bytes, files) = usages.iter().fold((0, 0), |(bytes, files), (_, usage)| {
+        (bytes + usage.0, files + usage.1)
+    });
+    println!("Total: {:.1} MB, {files} files", megabytes(bytes));
+
+    Ok(())
+}
+
+/// The size in bytes of the files in `folder` and its subfolders, and how many there are.
+fn disk_usage(folder: &Path) -> Result<(u64, usize)> {
+    let mut usage = (0, 0);
+    if !folder.is_dir() {
+        return Ok(usage);
+    }
+
+    for entry in read_dir(folder)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            let (bytes, files) = disk_usage(&entry.path())?;
+            usage = (usage.0 + bytes, usage.1 + files);
+        } else {
+            usage = (usage.0 + metadata.len(), usage.1 + 1);
+        }
+    }
+
+    Ok(usage)
+}
+
+/// Writes the configuration to `file`, to be imported on another machine. The tokens stay behind.
+pub fn export_config(project_dirs: &ProjectDirs, file: &Path) -> Result<()> {
+    let configuration = Configuration::load(project_dirs)?;
+    serde_json::to_writer_pretty(&File::create(file)?, &configuration)?;
+    println!("Configuration exported to {}", file.display());
 
     Ok(())
 }
 
+/// Copies the configuration to [`BACKUP_FILE`], before it gets replaced or reconfigured, returning
+/// the backup if there was a configuration to back up.
+fn backup(project_dirs: &ProjectDirs) -> Result<Option<PathBuf>> {
+    let config_file = project_dirs.config_dir().join(CONFIG_FILE);
+    if !config_file.exists() {
+        return Ok(None);
+    }
+
+    let backup_file = project_dirs.config_dir().join(BACKUP_FILE);
+    copy(&config_file, &backup_file)?;
+    Ok(Some(backup_file))
+}
+
+/// Replaces the configuration with the one exported in `file`, warning about the folders that
+/// don't exist on this machine.
+///
+/// The current configuration is backed up first, once the user confirmed replacing it when asked.
+pub fn import_config(project_dirs: &ProjectDirs, file: &Path, cli: &Cli) -> Result<()> {
+    // The skipped albums are still in `file`, to fix and import again.
+    let (configuration, _) = Configuration::read(file)?;
+
+    let downloads_root = configuration.downloads_root();
+    if !downloads_root.is_dir() {
+        warning!(
+            "the downloads folder {} doesn't exist",
+            downloads_root.display()
+        );
+    }
+    for local_album in &configuration.local_albums {
+        let path = configuration.album_path(local_album);
+        if !path.is_dir() {
+            warning!(
+                "the folder of {}, {}, doesn't exist",
+                local_album.name,
+                path.display()
+            );
+        }
+    }
+
+    #[cfg(feature = "interactive")]
+    if does_config_exist(project_dirs)
+        && crate::theme::is_interactive()
+        && !Confirm::with_theme(cli.theme().theme().as_ref())
+            .with_prompt("Replace the current configuration?")
+            .default(false)
+            .interact()?
+    {
+        println!("Configuration left as it was");
+        return Ok(());
+    }
+    #[cfg(not(feature = "interactive"))]
+    let _ = cli;
+
+    let backup_file = backup(project_dirs)?;
+    configuration.save(project_dirs)?;
+    println!("Configuration imported from {}", file.display());
+    if let Some(backup_file) = backup_file {
+        println!("The previous one is kept in {}", backup_file.display());
+    }
+
+    Ok(())
+}
+
+/// The "Google Photos" folder of the user's pictures, where a new configuration downloads to.
+fn default_downloads_root() -> PathBuf {
+    UserDirs::new()
+        .map(|user_dirs| {
+            user_dirs
+                .picture_dir()
+                .unwrap_or_else(|| user_dirs.home_dir())
+                .join("Google Photos")
+        })
+        .unwrap_or_else(|| PathBuf::from("Google Photos"))
+}
+
 pub fn does_config_exist(project_dirs: &ProjectDirs) -> bool {
     project_dirs.config_dir().join(CONFIG_FILE).exists()
 }
 
+#[cfg(feature = "interactive")]
 async fn add_new_album(
     configuration: &mut Configuration,
     project_dirs: &ProjectDirs,
+    theme: &dyn Theme,
+    cli: &Cli,
 ) -> Result<()> {
-    let album = pick_album(get_api().await?).await?;
-    let path = PathBuf::from_str(MANIFEST_DIR)?
-        .join("downloads")
-        .join(&album.title.trim());
-
-    configuration.local_albums.push(LocalAlbum {
-        path,
-        album_id: album.id,
-        name: album.title.trim().to_string(),
-    });
-
+    let album = match pick_album(get_api().await?, theme, cli.flatten).await? {
+        Some(album) => album,
+        None => return Ok(()),
+    };
+    let album_id = album.id.0.clone();
+    configuration.push_album(album, cli.dry_run);
+    if cli.dry_run {
+        return Ok(());
+    }
     configuration.save(project_dirs)?;
 
+    if Confirm::with_theme(theme)
+        .with_prompt("Synchronize it now?")
+        .default(true)
+        .interact()?
+    {
+        let cli = Cli {
+            album_id: Some(album_id),
+            ..cli.clone()
+        };
+        if let Err(e) = crate::synchronize(project_dirs, &cli).await {
+            eprintln!("Error: {e:#}");
+        }
+        // Picks up the status of the album.
+        *configuration = Configuration::load(project_dirs)?;
+    }
+
     Ok(())
 }
+
+/// Adds an album to the configuration, picked with `--album-index` or `--album-title` if
+/// given, without any menu, for setup scripts.
+pub async fn add_album(project_dirs: &ProjectDirs, cli: &Cli) -> Result<()> {
+    let mut configuration = Configuration::load(project_dirs)?;
+    let api = get_api().await?;
+
+    let album = match cli.album_choice() {
+        Some(choice) => find_album(api, cli.album_type, &choice).await?,
+        #[cfg(feature = "interactive")]
+        None => {
+            ensure_interactive()?;
+            let theme = cli.theme().theme();
+            match pick_album(api, theme.as_ref(), cli.flatten).await? {
+                Some(album) => album,
+                None => return Ok(()),
+            }
+        }
+        #[cfg(not(feature = "interactive"))]
+        None => {
+            bail!("Pick the album with --album-title or --album-index, this build has no menus")
+        }
+    };
+
+    if configuration
+        .local_albums
+        .iter()
+        .any(|local_album| local_album.album_id.0 == album.id.0)
+    {
+        info!("{} is already synchronized", album.title);
+        return Ok(());
+    }
+
+    if cli.dry_run {
+        configuration.push_album(album, true);
+        return Ok(());
+    }
+
+    info!("Added {}", album.title);
+    configuration.push_album(album, false);
+    configuration.save(project_dirs)
+}
+
+/// Renames one of the synchronized albums, moving its folder along if the user wants to.
+#[cfg(feature = "interactive")]
+fn rename_album(
+    configuration: &mut Configuration,
+    project_dirs: &ProjectDirs,
+    theme: &dyn Theme,
+) -> Result<()> {
+    let names: Vec<_> = configuration
+        .local_albums
+        .iter()
+        .map(|local_album| &local_album.name)
+        .collect();
+    let index = match menu::select(theme, "Select an album", &names)? {
+        Some(index) => index,
+        None => return Ok(()),
+    };
+    let local_album = &configuration.local_albums[index];
+
+    let name: String = Input::with_theme(theme)
+        .with_prompt("New name")
+        .with_initial_text(&local_album.name)
+        .interact_text()?;
+    let name = name.trim().to_string();
+
+    // The folder stays where it is, only its name changes.
+    let current_path = configuration.album_path(local_album);
+    let relative_path = local_album.path.with_file_name(sanitize_file_name(&name));
+    let path = configuration.downloads_root().join(&relative_path);
+    let should_move = path != current_path
+        && Confirm::with_theme(theme)
+            .with_prompt(format!("Move the folder to {}?", path.display()))
+            .default(false)
+            .interact()?;
+
+    let local_album = &mut configuration.local_albums[index];
+    if should_move {
+        if path.exists() {
+            bail!("{} already exists", path.display());
+        }
+        if current_path.exists() {
+            rename(&current_path, &path)?;
+        }
+        local_album.path = relative_path;
+    }
+    local_album.name = name;
+
+    configuration.save(project_dirs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn album(id: &str) -> Value {
+        json!({ "path": id, "album_id": id, "name": format!("Album {id}") })
+    }
+
+    fn album_ids(configuration: &Configuration) -> Vec<&str> {
+        configuration
+            .local_albums
+            .iter()
+            .map(|local_album| local_album.album_id.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn loading_skips_a_malformed_album() {
+        let (configuration, skipped) = Configuration::from_value(json!({
+            "local_albums": [album("a"), { "path": "b", "name": "No id" }, album("c")],
+            "downloads_root": "/photos",
+        }))
+        .unwrap();
+
+        assert_eq!(album_ids(&configuration), ["a", "c"]);
+        assert_eq!(skipped, 1);
+        assert_eq!(configuration.downloads_root(), Path::new("/photos"));
+    }
+
+    #[test]
+    fn loading_skips_a_wrong_typed_field() {
+        let (configuration, skipped) = Configuration::from_value(json!({
+            "local_albums": [album("a"), album("b")],
+            "downloads_root": "/photos",
+            "library_last_sync": 42,
+        }))
+        .unwrap();
+
+        assert_eq!(album_ids(&configuration), ["a", "b"]);
+        assert_eq!(skipped, 0);
+        assert!(configuration.library_last_sync.is_none());
+        assert_eq!(configuration.downloads_root(), Path::new("/photos"));
+    }
+
+    #[test]
+    fn loading_an_older_configuration_sets_the_default_downloads_folder() {
+        let (configuration, _) =
+            Configuration::from_value(json!({ "local_albums": [album("a")] })).unwrap();
+
+        assert_eq!(configuration.downloads_root, Some(default_downloads_root()));
+    }
+
+    #[test]
+    fn loading_rejects_what_isnt_an_object() {
+        assert!(Configuration::from_value(json!([album("a")])).is_err());
+    }
+
+    fn outcome(downloaded: usize, max_items: Option<usize>) -> SyncOutcome {
+        let tally = Tally {
+            downloaded,
+            ..Default::default()
+        };
+        SyncStatus::new(Utc::now(), &Ok(tally), false, max_items).outcome
+    }
+
+    #[test]
+    fn a_run_stopped_at_max_items_is_incomplete() {
+        assert!(outcome(10, Some(10)) == SyncOutcome::Incomplete);
+        assert!(outcome(3, Some(10)) == SyncOutcome::Succeeded);
+        assert!(outcome(10, None) == SyncOutcome::Succeeded);
+    }
+}
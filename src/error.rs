@@ -0,0 +1,43 @@
+use reqwest::StatusCode;
+use std::{io, time::Duration};
+
+/// What can go wrong while talking to Google and downloading, for the callers to tell what is
+/// worth retrying or skipping from what isn't.
+#[derive(Debug, thiserror::Error)]
+pub enum SyncError {
+    #[error("Google refused the credentials")]
+    Auth(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("network error")]
+    Network(#[source] reqwest::Error),
+    #[error("not found on Google")]
+    NotFound(#[source] reqwest::Error),
+    #[error("Google is limiting the requests, try again later")]
+    RateLimited(#[source] reqwest::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("unexpected response from Google")]
+    Serde(#[from] serde_json::Error),
+    #[error("timed out after {}s", .0.as_secs())]
+    TimedOut(Duration),
+    #[error("skipping {filename}, {reason}")]
+    ItemSkipped { filename: String, reason: String },
+}
+
+impl From<reqwest::Error> for SyncError {
+    fn from(error: reqwest::Error) -> Self {
+        match error.status() {
+            Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => {
+                SyncError::Auth(Box::new(error))
+            }
+            Some(StatusCode::NOT_FOUND) => SyncError::NotFound(error),
+            Some(StatusCode::TOO_MANY_REQUESTS) => SyncError::RateLimited(error),
+            _ => SyncError::Network(error),
+        }
+    }
+}
+
+impl From<yup_oauth2::Error> for SyncError {
+    fn from(error: yup_oauth2::Error) -> Self {
+        SyncError::Auth(Box::new(error))
+    }
+}
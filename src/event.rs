@@ -0,0 +1,47 @@
+use tokio::{
+    sync::mpsc::{self, UnboundedSender},
+    task::JoinHandle,
+};
+
+use crate::output::info;
+
+/// What happens to the items while synchronizing, for a frontend to display. The overall
+/// progress is reported separately, see [`crate::progress::Progress`].
+#[derive(Debug, Clone)]
+pub enum SyncEvent {
+    Started {
+        filename: String,
+    },
+    Skipped {
+        filename: String,
+        reason: String,
+    },
+    /// The error names the item.
+    Failed {
+        error: String,
+    },
+}
+
+/// Sends `event` to `sink`, if there is one.
+pub fn emit(sink: &Option<UnboundedSender<SyncEvent>>, event: SyncEvent) {
+    if let Some(sink) = sink {
+        // Nobody listening anymore isn't a reason to stop downloading.
+        let _ = sink.send(event);
+    }
+}
+
+/// Prints the events on the command line, until every clone of the returned sender is dropped.
+pub fn print_events() -> (UnboundedSender<SyncEvent>, JoinHandle<()>) {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let printer = tokio::spawn(async move {
+        while let Some(event) = receiver.recv().await {
+            match event {
+                SyncEvent::Started { filename } => info!("Downloading {filename}"),
+                SyncEvent::Skipped { filename, reason } => info!("Skipping {filename}, {reason}"),
+                SyncEvent::Failed { error } => eprintln!("Error: {error}"),
+            }
+        }
+    });
+
+    (sender, printer)
+}
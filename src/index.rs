@@ -0,0 +1,81 @@
+use anyhow::Result;
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    path::Path,
+};
+
+use crate::manifest::Manifest;
+
+pub const INDEX_FILE: &str = "index.html";
+pub const PLAYLIST_FILE: &str = "manifest.m3u";
+const NOMEDIA_FILE: &str = ".nomedia";
+
+/// Writes an `index.html` to browse the downloaded media of `folder`, and a `manifest.m3u`
+/// playlist of them.
+pub fn write_index(folder: &Path, title: &str) -> Result<()> {
+    let manifest = Manifest::load(folder)?;
+    let mut files: Vec<String> = manifest
+        .items
+        .values()
+        .filter(|file| folder.join(file).is_file())
+        .map(|file| file.to_string_lossy().replace('\\', "/"))
+        .collect();
+    files.sort();
+
+    let mut playlist = String::from("#EXTM3U\n");
+    for file in &files {
+        writeln!(playlist, "{file}")?;
+    }
+    fs::write(folder.join(PLAYLIST_FILE), playlist)?;
+
+    let title = escape_html(title);
+    let mut index = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n"
+    );
+    for file in &files {
+        let href = escape_html(&encode_path(file));
+        let is_video = Path::new(file)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .is_some_and(|ext| matches!(ext.as_str(), "mp4" | "mov" | "m4v" | "webm" | "3gp"));
+        if is_video {
+            writeln!(
+                index,
+                "<video src=\"{href}\" controls preload=\"none\" width=\"320\"></video>"
+            )?;
+        } else {
+            writeln!(index, "<a href=\"{href}\"><img src=\"{href}\" loading=\"lazy\" width=\"320\" alt=\"{}\"></a>", escape_html(file))?;
+        }
+    }
+    index.push_str("</body>\n</html>\n");
+    fs::write(folder.join(INDEX_FILE), index)?;
+
+    Ok(())
+}
+
+/// Writes a `.nomedia` file, hiding `folder` from the Android galleries.
+pub fn write_nomedia(folder: &Path) -> Result<()> {
+    File::create(folder.join(NOMEDIA_FILE))?;
+
+    Ok(())
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Percent-encodes a relative path for a link, keeping its slashes.
+fn encode_path(path: &str) -> String {
+    path.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
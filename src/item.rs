@@ -1,139 +1,1124 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+use chrono::{DateTime, NaiveDate, Utc};
 use exif::{In, Tag};
-use reqwest::Client;
+use image::ImageError;
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashSet,
     fs::{self, File},
-    io::{copy, BufReader, Cursor},
+    io::{self, BufReader},
+    ops::RangeInclusive,
     path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncWriteExt, BufWriter},
+    sync::mpsc::UnboundedSender,
 };
 use uuid::Uuid;
 
-use crate::api::{Id, MediaItemResponse, MediaItemSearchRequest};
+use crate::{
+    api::{Id, MediaItem, MediaMetadata},
+    audit::AuditLog,
+    client::http_client,
+    error::SyncError,
+    event::{emit, SyncEvent},
+    manifest::{Manifest, MANIFEST_FILE},
+    output::{info, warning},
+    progress::Progress,
+    storage::{extended_length_path, StorageBackend},
+};
 
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum MediaType {
     Photo,
     Video,
+    /// Neither a photo nor a video for Google, like a type it added since, downloaded as is.
+    Other,
 }
 
 #[derive(Clone)]
 pub struct Item {
+    id: Id,
     filename: String,
     base_url: String,
+    product_url: String,
+    media_metadata: MediaMetadata,
     media_type: MediaType,
 }
 
 impl Item {
-    pub fn new(filename: String, base_url: String, media_type: MediaType) -> Self {
+    /// The item for a media item listed by Google, [`MediaType::Other`] if it is neither a photo
+    /// nor a video.
+    pub fn from_media_item(media_item: MediaItem) -> Self {
+        let media_type = if media_item.media_metadata.photo.is_some() {
+            MediaType::Photo
+        } else if media_item.media_metadata.video.is_some() {
+            MediaType::Video
+        } else {
+            MediaType::Other
+        };
+
         Self {
-            filename,
-            base_url,
+            id: media_item.id,
+            filename: media_item.filename,
+            base_url: media_item.base_url,
+            product_url: media_item.product_url,
+            media_metadata: media_item.media_metadata,
             media_type,
         }
     }
+
+    pub fn id(&self) -> &Id {
+        &self.id
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub fn creation_time(&self) -> Option<&DateTime<Utc>> {
+        self.media_metadata.creation_time.as_ref()
+    }
+}
+
+/// Decides locally which of the listed items get downloaded.
+#[derive(Default, Clone)]
+pub struct Selection {
+    /// Skip the items created before that day.
+    pub since: Option<NaiveDate>,
+    /// Skip the items created after that day.
+    pub until: Option<NaiveDate>,
+    /// Only keep the photos, or the videos.
+    pub media_type: Option<MediaType>,
+    /// Only keep the items with these ids.
+    pub ids: Option<Arc<HashSet<String>>>,
+    /// Lowercase extensions, without the leading dot, of the files to skip.
+    pub excluded_extensions: Vec<String>,
+    /// Skip the items smaller than this, the ones without known dimensions are kept.
+    pub min_resolution: Option<Resolution>,
+    /// Only keep the items at these positions in the listing, counting from 1.
+    pub range: Option<RangeInclusive<usize>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Resolution {
+    /// Whether an image of `width` by `height` is at least this large, in either orientation.
+    fn fits_in(&self, width: u32, height: u32) -> bool {
+        let (short, long) = (width.min(height), width.max(height));
+        short >= self.width.min(self.height) && long >= self.width.max(self.height)
+    }
+}
+
+impl Selection {
+    /// Whether the item at `position` in the listing is in the range, if any.
+    pub fn is_in_range(&self, position: usize) -> bool {
+        self.range
+            .as_ref()
+            .is_none_or(|range| range.contains(&position))
+    }
+
+    /// Whether the listing can stop before `position`, all the items of the range being listed.
+    pub fn is_past_range(&self, position: usize) -> bool {
+        self.range
+            .as_ref()
+            .is_some_and(|range| position > *range.end())
+    }
+
+    pub fn matches(&self, item: &Item) -> bool {
+        let is_recent = match (self.since, item.creation_time()) {
+            (Some(since), Some(creation_time)) => creation_time.date_naive() >= since,
+            _ => true,
+        };
+        let is_old_enough = match (self.until, item.creation_time()) {
+            (Some(until), Some(creation_time)) => creation_time.date_naive() <= until,
+            _ => true,
+        };
+        let is_right_type = self
+            .media_type
+            .is_none_or(|media_type| media_type == item.media_type);
+        let is_listed = self
+            .ids
+            .as_ref()
+            .is_none_or(|ids| ids.contains(item.id.as_str()));
+        let is_excluded = Path::new(&item.filename)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .is_some_and(|ext| self.excluded_extensions.contains(&ext));
+
+        let is_large_enough = match (
+            self.min_resolution,
+            item.media_metadata.width,
+            item.media_metadata.height,
+        ) {
+            (Some(min_resolution), Some(width), Some(height)) => {
+                min_resolution.fits_in(width, height)
+            }
+            _ => true,
+        };
+
+        is_recent && is_old_enough && is_right_type && is_listed && !is_excluded && is_large_enough
+    }
+}
+
+/// How large the photos are downloaded, the videos always come as they were uploaded.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quality {
+    #[default]
+    Original,
+    /// Scaled down to fit in a square this many pixels wide.
+    MaxDimension(u32),
+}
+
+/// What to do with the items that are neither photos nor videos, see [`MediaType::Other`].
+#[derive(Default, Clone, Copy, clap::ArgEnum)]
+pub enum UnknownMedia {
+    Skip,
+    /// As is, without any conversion.
+    #[default]
+    Download,
+    /// Fail the item, to find out about them.
+    Error,
+}
+
+#[derive(Default, Clone)]
+pub struct DownloadOptions {
+    /// Write a `.json` sidecar with the Google metadata next to each downloaded file.
+    pub write_metadata: bool,
+    /// Skip the files bigger than this many bytes.
+    pub max_size: Option<u64>,
+    /// Download the photos again when their dimensions differ from Google's.
+    pub verify_dimensions: bool,
+    /// Where to put each item inside the album folder, see [`expand_folder_template`].
+    pub folder_template: Option<String>,
+    /// Stop each album after downloading this many items.
+    pub max_items: Option<usize>,
+    /// Prefix the file names with the position of the items in their album, like `0001_`.
+    pub sequence_prefix: bool,
+    /// How large the photos are downloaded.
+    pub quality: Quality,
+    /// Convert the HEIC photos to JPEG, see [`convert_heic`].
+    pub convert_heic: bool,
+    /// Keep the HEIC photos next to their JPEG conversion.
+    pub keep_heic: bool,
+    /// Download the motion of the Live Photos next to them, see [`download_motion`].
+    pub live_photos: bool,
+    /// How many times a download failing on a network error is tried again.
+    pub max_retries: u32,
+    pub unknown_media: UnknownMedia,
+    /// Download the most recently created items of each page first.
+    pub newest_first: bool,
+    /// Give up on the items taking longer than this to download.
+    pub item_timeout: Option<Duration>,
+    /// Where to report what happens to each item, see [`SyncEvent`].
+    pub events: Option<UnboundedSender<SyncEvent>>,
+    /// Where to record the downloads, for the callers of [`download_file`] to do so.
+    pub audit: Option<Arc<AuditLog>>,
 }
 
-async fn _list_items(client: &Client, album_id: &Id) -> Result<Vec<Item>> {
-    let url = "https://photoslibrary.googleapis.com/v1/mediaItems:search";
+/// What can go wrong while naming a downloaded item, telling which item it was.
+#[derive(Debug, thiserror::Error)]
+pub enum ItemError {
+    #[error("couldn't open the download of {filename} ({id})")]
+    Open {
+        filename: String,
+        id: Id,
+        source: io::Error,
+    },
+    #[error("couldn't read the EXIF data of {filename} ({id})")]
+    Exif {
+        filename: String,
+        id: Id,
+        source: exif::Error,
+    },
+}
+
+/// How much of a download is kept in memory before being written.
+const WRITE_BUFFER_SIZE: usize = 256 * 1024;
 
-    let request_body = serde_json::to_string(&MediaItemSearchRequest {
-        album_id,
-        page_size: Some(100),
-        page_token: None,
-    })?;
+/// How many times a photo is downloaded before giving up on getting the right dimensions.
+const DOWNLOAD_ATTEMPTS: u32 = 3;
 
-    let response = client.post(url).body(request_body).send().await?;
+pub enum Outcome {
+    /// The file went there, relative to the output folder, along with its companions: the motion
+    /// of a live photo, or the HEIC kept next to its JPEG.
+    Downloaded {
+        file: PathBuf,
+        companions: Vec<PathBuf>,
+    },
+    Skipped,
+}
 
-    let media_response: MediaItemResponse = response.json().await?;
-    if let Some(media_items) = media_response.media_items {
-        Ok(media_items
-            .into_iter()
-            .filter_map(|item| {
-                let media_type = if item.media_metadata.photo.is_some() {
-                    MediaType::Photo
-                } else if item.media_metadata.video.is_some() {
-                    MediaType::Video
-                } else {
-                    return None;
-                };
+/// The provenance of a downloaded file, as written in its sidecar.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Sidecar<'a> {
+    id: &'a Id,
+    filename: &'a str,
+    media_metadata: &'a MediaMetadata,
+    product_url: &'a str,
+}
 
-                Some(Item::new(item.filename, item.base_url, media_type))
-            })
-            .collect())
+/// `position` is where the item stands in its album, counting from 1, see
+/// [`DownloadOptions::sequence_prefix`].
+pub async fn download_file(
+    item: &Item,
+    position: usize,
+    storage: &dyn StorageBackend,
+    options: &DownloadOptions,
+    progress: &Progress,
+) -> Result<Outcome> {
+    emit(
+        &options.events,
+        SyncEvent::Started {
+            filename: item.filename.clone(),
+        },
+    );
+    let url = download_url(item, options.quality);
+    if item.media_type == MediaType::Other {
+        match options.unknown_media {
+            UnknownMedia::Skip => {
+                emit(
+                    &options.events,
+                    SyncEvent::Skipped {
+                        filename: item.filename.clone(),
+                        reason: "it is neither a photo nor a video".to_string(),
+                    },
+                );
+                return Ok(Outcome::Skipped);
+            }
+            UnknownMedia::Download => warning!(
+                "{} ({}) is neither a photo nor a video, downloading it as is",
+                item.filename,
+                item.id
+            ),
+            UnknownMedia::Error => bail!(
+                "{} ({}) is neither a photo nor a video",
+                item.filename,
+                item.id
+            ),
+        }
+    }
+    // Scaled down photos can't have the dimensions Google announces.
+    let verify_dimensions =
+        options.verify_dimensions && matches!(options.quality, Quality::Original);
+
+    let subfolder = match (&options.folder_template, item.creation_time()) {
+        (Some(template), Some(creation_time)) => {
+            PathBuf::from(expand_folder_template(template, creation_time))
+        }
+        // Undated items stay at the top of the folder.
+        _ => PathBuf::new(),
+    };
+    let output_folder = create_output_folder(storage.staging_folder(), &subfolder)?;
+
+    let temp_filename = Uuid::new_v4();
+    let temp_filename = output_folder.join(format!("{temp_filename}"));
+
+    let mut attempt = 1;
+    loop {
+        let fetched = fetch(item, &url, &temp_filename, options, progress);
+        let fetched = match options.item_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, fetched)
+                .await
+                .unwrap_or(Err(SyncError::TimedOut(timeout))),
+            None => fetched.await,
+        };
+        match fetched {
+            Ok(()) => {}
+            Err(SyncError::ItemSkipped { filename, reason }) => {
+                emit(&options.events, SyncEvent::Skipped { filename, reason });
+                return Ok(Outcome::Skipped);
+            }
+            Err(e) => {
+                // Whatever was written of it.
+                let _ = fs::remove_file(&temp_filename);
+                return Err(e.into());
+            }
+        }
+        if !verify_dimensions || has_expected_dimensions(&temp_filename, item) {
+            break;
+        }
+        if attempt == DOWNLOAD_ATTEMPTS {
+            fs::remove_file(&temp_filename)?;
+            bail!(
+                "{} doesn't have the expected dimensions after {DOWNLOAD_ATTEMPTS} attempts",
+                item.filename
+            );
+        }
+
+        warning!(
+            "{} doesn't have the expected dimensions, downloading it again",
+            item.filename
+        );
+        attempt += 1;
+    }
+
+    let position = Some(position).filter(|_| options.sequence_prefix);
+    let downloaded = match best_file_name(&temp_filename, item, position, &output_folder) {
+        Ok(downloaded) => downloaded,
+        Err(e) => {
+            // Not to leave it behind, named after nothing.
+            let _ = fs::remove_file(&temp_filename);
+            return Err(e.into());
+        }
+    };
+    std::fs::rename(temp_filename, &downloaded)?;
+    let filename = if options.convert_heic && is_heic(&downloaded) {
+        convert_heic(item, downloaded.clone(), options, progress).await
     } else {
-        Ok(vec![])
+        downloaded.clone()
+    };
+
+    let mut companions = vec![];
+    if downloaded != filename && downloaded.exists() {
+        // The HEIC kept next to its JPEG.
+        companions.push(downloaded);
+    }
+    if options.live_photos && may_have_motion(item) {
+        companions.extend(download_motion(item, &filename).await);
+    }
+    let mut staged = vec![filename.clone()];
+    staged.extend(companions.iter().cloned());
+    if options.write_metadata {
+        staged.push(write_sidecar(item, &filename)?);
+    }
+
+    let key = |path: &Path| subfolder.join(path.file_name().expect("Built out of a file name"));
+    for file in &staged {
+        storage.store(file, &key(file))?;
     }
+
+    Ok(Outcome::Downloaded {
+        file: key(&filename),
+        companions: companions.iter().map(|file| key(file)).collect(),
+    })
 }
 
-pub async fn download_file<P>(item: &Item, output_folder: P) -> Result<()>
+/// Where to download `item` from, at `quality` if it is a photo.
+fn download_url(item: &Item, quality: Quality) -> String {
+    match (&item.media_type, quality) {
+        // Only photos can be scaled down.
+        (MediaType::Photo, Quality::Original) | (MediaType::Other, _) => {
+            format!("{}={}", item.base_url, "d")
+        }
+        (MediaType::Photo, Quality::MaxDimension(size)) => {
+            format!("{}=w{size}-h{size}", item.base_url)
+        }
+        (MediaType::Video, _) => format!("{}={}", item.base_url, "dv"),
+    }
+}
+
+fn is_heic(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| ext == "heic" || ext == "heif")
+}
+
+/// Downloads a JPEG of a HEIC photo next to it, returning its path. Unless
+/// [`DownloadOptions::keep_heic`], the HEIC is removed.
+///
+/// Google does the transcoding when asked for the photo at its own size, at the cost of its EXIF.
+/// Whatever goes wrong, the HEIC is kept.
+async fn convert_heic(
+    item: &Item,
+    heic_path: PathBuf,
+    options: &DownloadOptions,
+    progress: &Progress,
+) -> PathBuf {
+    let (width, height) = match (item.media_metadata.width, item.media_metadata.height) {
+        (Some(width), Some(height)) => (width, height),
+        _ => {
+            warning!(
+                "the size of {} is unknown, keeping it as HEIC",
+                item.filename
+            );
+            return heic_path;
+        }
+    };
+
+    let url = format!("{}=w{width}-h{height}", item.base_url);
+    let jpeg_path = unique_path(heic_path.with_extension("jpg"), &heic_path);
+    if let Err(e) = fetch(item, &url, &jpeg_path, options, progress).await {
+        warning!(
+            "couldn't convert {} to JPEG, keeping it as HEIC: {e}",
+            item.filename
+        );
+        let _ = fs::remove_file(&jpeg_path);
+        return heic_path;
+    }
+
+    if !options.keep_heic {
+        if let Err(e) = fs::remove_file(&heic_path) {
+            warning!("couldn't remove {}: {e}", heic_path.display());
+        }
+    }
+    jpeg_path
+}
+
+/// Whether the photo may be a Live Photo or a motion photo, judging by its name as Google doesn't
+/// tell: iPhones take HEIC photos, Pixels name them `MVIMG_` or `.MP.jpg`.
+fn may_have_motion(item: &Item) -> bool {
+    let filename = item.filename.to_lowercase();
+    matches!(item.media_type, MediaType::Photo)
+        && (filename.ends_with(".heic")
+            || filename.starts_with("mvimg_")
+            || filename.contains(".mp."))
+}
+
+/// Downloads the motion of a Live Photo next to its still at `photo_path`, with the same name,
+/// returning its path.
+///
+/// Only the photos with a motion come as a video, the others are left alone. Whatever goes wrong,
+/// the still is kept.
+async fn download_motion(item: &Item, photo_path: &Path) -> Option<PathBuf> {
+    let result = async {
+        let response = http_client()?
+            .get(format!("{}=dv", item.base_url))
+            .send()
+            .await?
+            .error_for_status()?;
+        let extension = match response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+        {
+            Some("video/quicktime") => "mov",
+            Some(content_type) if content_type.starts_with("video/") => "mp4",
+            _ => return Ok(None),
+        };
+
+        let motion_path = unique_path(photo_path.with_extension(extension), photo_path);
+        tokio::fs::write(&motion_path, response.bytes().await?).await?;
+        Ok::<_, anyhow::Error>(Some(motion_path))
+    };
+
+    result.await.unwrap_or_else(|e| {
+        warning!("couldn't download the motion of {}: {e}", item.filename);
+        None
+    })
+}
+
+/// Replaces `{year}`, `{month}` and `{day}` in `template` with the creation date of an item,
+/// giving the subfolder it goes to, like `2023/07` out of `{year}/{month}`.
+fn expand_folder_template(template: &str, creation_time: &DateTime<Utc>) -> String {
+    template
+        .replace("{year}", &creation_time.format("%Y").to_string())
+        .replace("{month}", &creation_time.format("%m").to_string())
+        .replace("{day}", &creation_time.format("%d").to_string())
+}
+
+/// Downloads `url` into `file_path`, failing with [`SyncError::ItemSkipped`] if the file is over
+/// the maximum size.
+async fn fetch(
+    item: &Item,
+    url: &str,
+    file_path: &Path,
+    options: &DownloadOptions,
+    progress: &Progress,
+) -> Result<(), SyncError> {
+    let mut response = http_client()?.get(url).send().await?.error_for_status()?;
+
+    let is_too_large = |size| options.max_size.is_some_and(|max_size| size > max_size);
+    if let Some(size) = response.content_length().filter(|&size| is_too_large(size)) {
+        return Err(SyncError::ItemSkipped {
+            filename: item.filename.clone(),
+            reason: format!("{size} bytes is over the maximum size"),
+        });
+    }
+
+    // Written asynchronously, not to hold up the other downloads of the worker thread, and
+    // buffered, the chunks being too small to be worth a write each.
+    let mut file =
+        BufWriter::with_capacity(WRITE_BUFFER_SIZE, tokio::fs::File::create(file_path).await?);
+
+    let mut size = 0;
+    while let Some(chunk) = response.chunk().await? {
+        progress.add_bytes(chunk.len() as u64);
+        // Without a Content-Length, the size is only known while downloading.
+        size += chunk.len() as u64;
+        if is_too_large(size) {
+            drop(file);
+            tokio::fs::remove_file(file_path).await?;
+            return Err(SyncError::ItemSkipped {
+                filename: item.filename.clone(),
+                reason: "it is over the maximum size".to_string(),
+            });
+        }
+
+        file.write_all(&chunk).await?;
+    }
+    // Waits for the last writes, before the file is renamed or read.
+    file.flush().await?;
+
+    Ok(())
+}
+
+/// Whether the downloaded photo has the dimensions announced by Google, when they can be checked.
+fn has_expected_dimensions<P>(file_path: P, item: &Item) -> bool
 where
     P: AsRef<Path>,
 {
-    println!("Downloading {}", item.filename);
-    let url = match &item.media_type {
-        MediaType::Photo => format!("{}={}", item.base_url, "d"),
-        MediaType::Video => format!("{}={}", item.base_url, "dv"),
+    let expected = match (
+        &item.media_type,
+        item.media_metadata.width,
+        item.media_metadata.height,
+    ) {
+        (MediaType::Photo, Some(width), Some(height)) => (width, height),
+        _ => return true,
     };
 
-    fs::create_dir_all(&output_folder)?;
+    let dimensions = image::io::Reader::open(file_path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(ImageError::IoError)
+        .and_then(|reader| reader.into_dimensions());
+    match dimensions {
+        // Depending on the EXIF orientation, the dimensions may come swapped.
+        Ok((width, height)) => (width, height) == expected || (height, width) == expected,
+        // Formats like HEIC can't be decoded here, there is no telling.
+        Err(ImageError::Unsupported(_)) => true,
+        Err(_) => false,
+    }
+}
 
-    let mut response = reqwest::get(url).await?;
+/// Writes the sidecar of the file at `file_path`, returning its path.
+fn write_sidecar<P>(item: &Item, file_path: P) -> Result<PathBuf>
+where
+    P: AsRef<Path>,
+{
+    let mut sidecar_path = file_path.as_ref().as_os_str().to_owned();
+    sidecar_path.push(".json");
+    let sidecar_path = PathBuf::from(sidecar_path);
 
-    let temp_filename = Uuid::new_v4();
-    let temp_filename = output_folder.as_ref().join(format!("{temp_filename}"));
-    let mut file = File::create(&temp_filename)?;
+    let sidecar = Sidecar {
+        id: &item.id,
+        filename: &item.filename,
+        media_metadata: &item.media_metadata,
+        product_url: &item.product_url,
+    };
+    serde_json::to_writer_pretty(&File::create(&sidecar_path)?, &sidecar)?;
 
-    while let Some(chunk) = response.chunk().await? {
-        let mut cursor = Cursor::new(chunk);
-        copy(&mut cursor, &mut file)?;
-    }
+    Ok(sidecar_path)
+}
 
-    let filename = best_file_name(&temp_filename, item, &output_folder)?;
-    std::fs::rename(temp_filename, &filename)?;
+/// Turns a name coming from Google into a valid file or folder name on every platform.
+///
+/// Path separators and other forbidden characters become `_`, so that "2023/Trips" doesn't
+/// create nested folders, and reserved names like `CON` get prefixed.
+pub fn sanitize_file_name(name: &str) -> String {
+    const RESERVED: &[&str] = &[
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
 
-    Ok(())
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    // Windows drops the trailing dots and spaces.
+    let name = name.trim().trim_end_matches(['.', ' ']);
+
+    let stem = name.split('.').next().unwrap_or_default();
+    if name.is_empty() {
+        "untitled".to_string()
+    } else if RESERVED.contains(&stem.to_uppercase().as_str()) {
+        format!("_{name}")
+    } else {
+        name.to_string()
+    }
 }
 
-fn best_file_name<P1, P2>(file_path: P1, item: &Item, output_folder: P2) -> Result<PathBuf>
+/// The name given to a downloaded item, prefixed with its `position` if there is one.
+fn best_file_name<P1, P2>(
+    file_path: P1,
+    item: &Item,
+    position: Option<usize>,
+    output_folder: P2,
+) -> Result<PathBuf, ItemError>
 where
     P1: AsRef<Path>,
     P2: AsRef<Path>,
 {
-    let file_name = match item.media_type {
-        MediaType::Photo => match PathBuf::from(&item.filename)
-            .extension()
-            .map(|ext| ext.to_string_lossy().to_lowercase())
-        {
-            Some(ext) => match ext.as_str() {
-                "jpg" | "jpeg" | "png" => {
-                    let ext = if ext.as_str() == "jpeg" {
-                        "jpg"
-                    } else {
-                        ext.as_str()
-                    };
-
-                    let file = File::open(&file_path)?;
-                    let mut bufreader = BufReader::new(&file);
-                    let exif_reader = exif::Reader::new();
-                    let exif = exif_reader.read_from_container(&mut bufreader)?;
-                    if let Some(field) = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY) {
-                        let sanitize_date = field
-                            .display_value()
-                            .to_string()
-                            .replace(':', "-")
-                            .replace(' ', "_");
-                        let name = format!("{}.{}", sanitize_date, ext);
-                        output_folder.as_ref().join(&name)
-                    } else {
-                        output_folder.as_ref().join(&item.filename)
-                    }
-                }
-                _ => output_folder.as_ref().join(&item.filename),
-            },
-            None => output_folder.as_ref().join(&item.filename),
-        },
-        MediaType::Video => output_folder.as_ref().join(&item.filename),
+    let date_file_name = match item.media_type {
+        MediaType::Photo => date_file_name(file_path, &item.filename)
+            .map_err(|source| ItemError::Open {
+                filename: item.filename.clone(),
+                id: item.id.clone(),
+                source,
+            })?
+            .map_err(|source| ItemError::Exif {
+                filename: item.filename.clone(),
+                id: item.id.clone(),
+                source,
+            }),
+        MediaType::Video | MediaType::Other => Ok(None),
     };
+    let file_name = match date_file_name {
+        Ok(Some(file_name)) => file_name,
+        Ok(None) => sanitize_file_name(&item.filename),
+        // Naming is best effort, a bad EXIF mustn't cost the download itself, even a truncated
+        // one failing to read.
+        Err(e) => {
+            warning!("{:#}, keeping its name", anyhow::Error::new(e));
+            sanitize_file_name(&item.filename)
+        }
+    };
+
+    let path = match position {
+        Some(position) => output_folder
+            .as_ref()
+            .join(format!("{position:04}_{file_name}")),
+        None => output_folder.as_ref().join(file_name),
+    };
+    Ok(path)
+}
+
+/// Builds a file name out of the EXIF capture date of a photo, if it has one.
+///
+/// `filename` is the original name of the photo, giving away its format.
+///
+/// Fails on the outside if the photo can't be opened, and on the inside if its EXIF data can't be
+/// read, I/O errors while reading it included.
+fn date_file_name<P>(
+    file_path: P,
+    filename: &str,
+) -> io::Result<Result<Option<String>, exif::Error>>
+where
+    P: AsRef<Path>,
+{
+    let ext = match Path::new(filename)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+    {
+        Some(ext) => ext,
+        None => return Ok(Ok(None)),
+    };
+    let ext = match ext.as_str() {
+        "jpeg" => "jpg",
+        "jpg" | "png" | "heic" | "heif" => ext.as_str(),
+        _ => return Ok(Ok(None)),
+    };
+
+    let file = File::open(file_path)?;
+    let mut bufreader = BufReader::new(&file);
+    let exif = match exif::Reader::new().read_from_container(&mut bufreader) {
+        Ok(exif) => exif,
+        // Most PNGs, screenshots especially, carry no EXIF at all.
+        Err(exif::Error::NotFound(_)) => return Ok(Ok(None)),
+        Err(e) => return Ok(Err(e)),
+    };
+
+    let file_name = exif
+        .get_field(Tag::DateTimeOriginal, In::PRIMARY)
+        .map(|field| {
+            let sanitize_date = field
+                .display_value()
+                .to_string()
+                .replace(':', "-")
+                .replace(' ', "_");
+            format!("{}.{}", sanitize_date, ext)
+        });
+
+    Ok(Ok(file_name))
+}
+
+/// Renames the photos already in `folder` and its subfolders to match the current naming
+/// scheme, returning how many were renamed.
+///
+/// Their sidecars follow along, and names colliding with another file get a numbered suffix.
+/// Photos stay in their subfolder, and the subfolders with a manifest of their own are left to
+/// it.
+pub fn reorganize_folder<P>(folder: P) -> Result<usize>
+where
+    P: AsRef<Path>,
+{
+    let folder = extended_length_path(folder.as_ref())?;
+    let mut manifest = Manifest::load(&folder)?;
+
+    let mut renamed = 0;
+    let mut subfolders = vec![PathBuf::new()];
+    while let Some(subfolder) = subfolders.pop() {
+        let mut current_folder = folder.clone();
+        // Pushed one by one, as extended-length paths don't take `/` for a separator.
+        current_folder.extend(subfolder.components());
+        for entry in fs::read_dir(&current_folder)? {
+            let path = entry?.path();
+            let filename = match path.file_name() {
+                Some(filename) if path.is_file() => filename.to_string_lossy().to_string(),
+                Some(filename) if path.is_dir() && !path.join(MANIFEST_FILE).exists() => {
+                    subfolders.push(subfolder.join(filename));
+                    continue;
+                }
+                _ => continue,
+            };
+
+            let file_name = match date_file_name(&path, &filename).map_err(exif::Error::Io) {
+                Ok(Ok(Some(file_name))) => file_name,
+                Ok(Ok(None)) => continue,
+                Ok(Err(e)) | Err(e) => {
+                    warning!("couldn't read the date of {filename}, keeping its name: {e}");
+                    continue;
+                }
+            };
+
+            let target = unique_path(current_folder.join(file_name), &path);
+            if target == path {
+                continue;
+            }
+
+            info!("Renaming {} to {}", path.display(), target.display());
+            fs::rename(&path, &target)?;
+            if let Some(target_name) = target.file_name() {
+                manifest.rename(&subfolder.join(&filename), &subfolder.join(target_name));
+            }
+            let mut sidecar_path = path.into_os_string();
+            sidecar_path.push(".json");
+            let sidecar_path = PathBuf::from(sidecar_path);
+            if sidecar_path.exists() {
+                let mut target_sidecar_path = target.into_os_string();
+                target_sidecar_path.push(".json");
+                fs::rename(sidecar_path, target_sidecar_path)?;
+            }
+            renamed += 1;
+        }
+    }
+    if renamed > 0 {
+        manifest.save(&folder)?;
+    }
+
+    Ok(renamed)
+}
+
+/// Creates `subfolder` in `staging_folder`, in its extended-length form on Windows, so that deep
+/// subfolders don't run into `MAX_PATH` either.
+fn create_output_folder(staging_folder: &Path, subfolder: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(staging_folder)?;
+    let mut output_folder = extended_length_path(staging_folder)?;
+    // Pushed one by one, as extended-length paths don't take `/` for a separator.
+    output_folder.extend(subfolder.components());
+    fs::create_dir_all(&output_folder)?;
+
+    Ok(output_folder)
+}
+
+/// Returns `path`, or a numbered variant of it if it is already taken by another file than
+/// `current`.
+fn unique_path(path: PathBuf, current: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut candidate = path.clone();
+    let mut index = 1;
+    while candidate != current && candidate.exists() {
+        candidate = path.with_file_name(format!("{stem}_{index}{extension}"));
+        index += 1;
+    }
+
+    candidate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::LocalStorage;
+    use serde_json::json;
 
-    Ok(file_name)
+    /// An item of a type Google added since, with neither photo nor video metadata.
+    fn unknown_item() -> Item {
+        let media_item: MediaItem = serde_json::from_value(json!({
+            "id": "unknown",
+            "filename": "unknown.xyz",
+            "baseUrl": "https://example.com/unknown",
+            "productUrl": "https://photos.google.com/unknown",
+            "mediaMetadata": { "creationTime": "2023-07-01T12:00:00Z" },
+        }))
+        .unwrap();
+        Item::from_media_item(media_item)
+    }
+
+    async fn download_unknown(unknown_media: UnknownMedia) -> Result<Outcome> {
+        let options = DownloadOptions {
+            unknown_media,
+            ..Default::default()
+        };
+        // Nothing is written, the item is handled before any download.
+        let storage = LocalStorage::new("unused");
+        download_file(&unknown_item(), 1, &storage, &options, &Progress::new(None)).await
+    }
+
+    /// Serves `body` as a video to a single request, returning the base URL to request it from.
+    async fn serve_once(body: &'static [u8]) -> String {
+        use tokio::{io::AsyncReadExt, net::TcpListener};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request).await.unwrap();
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: video/mp4\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n",
+                body.len()
+            );
+            stream.write_all(header.as_bytes()).await.unwrap();
+            stream.write_all(body).await.unwrap();
+        });
+        format!("http://{address}/video")
+    }
+
+    /// Downloads a video served locally into a new temporary folder, under `folder_template`.
+    async fn download_video(folder_template: &str) -> (PathBuf, Result<Outcome>) {
+        let media_item: MediaItem = serde_json::from_value(json!({
+            "id": "video",
+            "filename": "video.mp4",
+            "baseUrl": serve_once(b"video").await,
+            "productUrl": "https://photos.google.com/video",
+            "mediaMetadata": { "creationTime": "2023-07-01T12:00:00Z", "video": {} },
+        }))
+        .unwrap();
+        let options = DownloadOptions {
+            folder_template: Some(folder_template.to_string()),
+            ..Default::default()
+        };
+        let root = std::env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        let storage = LocalStorage::new(&root);
+        let item = Item::from_media_item(media_item);
+        let outcome = download_file(&item, 1, &storage, &options, &Progress::new(None)).await;
+        (root, outcome)
+    }
+
+    #[tokio::test]
+    async fn stores_into_the_template_folder() {
+        let (root, outcome) = download_video("{year}/{month}").await;
+        let stored = fs::read(root.join("2023/07/video.mp4"));
+        fs::remove_dir_all(&root).unwrap();
+
+        match outcome {
+            Ok(Outcome::Downloaded { file, companions }) => {
+                assert_eq!(file, Path::new("2023/07/video.mp4"));
+                assert!(companions.is_empty());
+            }
+            _ => panic!("the video wasn't downloaded"),
+        }
+        assert_eq!(stored.unwrap(), b"video");
+    }
+
+    #[cfg(windows)]
+    #[tokio::test]
+    async fn stores_past_max_path() {
+        // Separated by `/`, as in any folder template.
+        let template = (0..8)
+            .map(|index| format!("{index}{}", "a".repeat(40)))
+            .chain(std::iter::once("{year}".to_string()))
+            .collect::<Vec<_>>()
+            .join("/");
+
+        let (root, outcome) = download_video(&template).await;
+        let root = extended_length_path(&root).unwrap();
+        let file = match outcome {
+            Ok(Outcome::Downloaded { file, .. }) => file,
+            Ok(Outcome::Skipped) => panic!("the video was skipped"),
+            Err(e) => panic!("the video wasn't downloaded: {e:#}"),
+        };
+        let mut stored = root.clone();
+        stored.extend(file.components());
+        let content = fs::read(&stored);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(stored.as_os_str().len() > 260);
+        assert_eq!(content.unwrap(), b"video");
+    }
+
+    /// A photo downloaded into a new temporary folder, with `content` for its file.
+    fn downloaded_photo(content: &[u8]) -> (PathBuf, Item) {
+        let folder = std::env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(folder.join("download"), content).unwrap();
+        let media_item: MediaItem = serde_json::from_value(json!({
+            "id": "photo",
+            "filename": "IMG_0001.jpg",
+            "baseUrl": "https://example.com/photo",
+            "productUrl": "https://photos.google.com/photo",
+            "mediaMetadata": { "photo": {} },
+        }))
+        .unwrap();
+        (folder, Item::from_media_item(media_item))
+    }
+
+    fn name_of(content: &[u8]) -> Result<PathBuf, ItemError> {
+        let (folder, item) = downloaded_photo(content);
+        let name = best_file_name(folder.join("download"), &item, None, &folder)
+            .map(|path| path.strip_prefix(&folder).unwrap().to_path_buf());
+        fs::remove_dir_all(folder).unwrap();
+        name
+    }
+
+    #[test]
+    fn a_garbage_photo_keeps_its_name() {
+        assert_eq!(name_of(b"not a photo").unwrap(), Path::new("IMG_0001.jpg"));
+    }
+
+    #[test]
+    fn a_truncated_photo_keeps_its_name() {
+        // The start of an APP1 segment announcing more EXIF data than there is.
+        let truncated = b"\xff\xd8\xff\xe1\x10\x00Exif\x00\x00MM\x00\x2a";
+
+        assert_eq!(name_of(truncated).unwrap(), Path::new("IMG_0001.jpg"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_photo_failing_to_read_keeps_its_name() {
+        // A folder opens fine on Unix, reading it is what fails.
+        let (folder, item) = downloaded_photo(b"");
+        fs::create_dir(folder.join("folder")).unwrap();
+        let name = best_file_name(folder.join("folder"), &item, None, &folder);
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(name.unwrap(), folder.join("IMG_0001.jpg"));
+    }
+
+    #[test]
+    fn a_photo_that_cant_be_opened_fails() {
+        let (folder, item) = downloaded_photo(b"");
+        let name = best_file_name(folder.join("missing"), &item, None, &folder);
+        fs::remove_dir_all(folder).unwrap();
+
+        assert!(matches!(name, Err(ItemError::Open { .. })));
+    }
+
+    /// A JPEG made of an EXIF segment with only the date it was taken at.
+    fn photo_taken_at(date: &str) -> Vec<u8> {
+        let field = exif::Field {
+            tag: Tag::DateTimeOriginal,
+            ifd_num: In::PRIMARY,
+            value: exif::Value::Ascii(vec![date.as_bytes().to_vec()]),
+        };
+        let mut writer = exif::experimental::Writer::new();
+        writer.push_field(&field);
+        let mut tiff = io::Cursor::new(vec![]);
+        writer.write(&mut tiff, false).unwrap();
+        let tiff = tiff.into_inner();
+
+        let mut photo = b"\xff\xd8\xff\xe1".to_vec();
+        photo.extend(((tiff.len() + 8) as u16).to_be_bytes());
+        photo.extend(b"Exif\x00\x00");
+        photo.extend(tiff);
+        photo.extend(b"\xff\xd9");
+        photo
+    }
+
+    #[test]
+    fn reorganizes_the_template_folders() {
+        let folder = std::env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir_all(folder.join("2023/07")).unwrap();
+        fs::write(
+            folder.join("2023/07/IMG_0001.jpg"),
+            photo_taken_at("2023:07:14 12:30:00"),
+        )
+        .unwrap();
+        fs::write(folder.join("2023/07/IMG_0001.jpg.json"), "{}").unwrap();
+        let mut manifest = Manifest::default();
+        manifest.insert(
+            "photo".to_string(),
+            PathBuf::from("2023/07/IMG_0001.jpg"),
+            vec![],
+        );
+        manifest.save(&folder).unwrap();
+
+        let renamed = reorganize_folder(&folder).unwrap();
+        let renamed_file = folder.join("2023/07/2023-07-14_12-30-00.jpg");
+        let (moved, sidecar_moved) = (
+            renamed_file.is_file(),
+            folder
+                .join("2023/07/2023-07-14_12-30-00.jpg.json")
+                .is_file(),
+        );
+        let files: Vec<_> = Manifest::load(&folder)
+            .unwrap()
+            .files("photo")
+            .cloned()
+            .collect();
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(renamed, 1);
+        assert!(moved);
+        assert!(sidecar_moved);
+        assert_eq!(
+            files,
+            vec![PathBuf::from("2023/07/2023-07-14_12-30-00.jpg")]
+        );
+    }
+
+    #[test]
+    fn sanitizes_file_names() {
+        let cases = [
+            ("IMG_0001.jpg", "IMG_0001.jpg"),
+            ("2023/Trips", "2023_Trips"),
+            ("a\\b:c*d?e\"f<g>h|i", "a_b_c_d_e_f_g_h_i"),
+            ("tab\there", "tab_here"),
+            ("  Holidays  ", "Holidays"),
+            ("Summer...", "Summer"),
+            ("Summer. . ", "Summer"),
+            ("CON", "_CON"),
+            ("con.txt", "_con.txt"),
+            ("LPT9.tar.gz", "_LPT9.tar.gz"),
+            ("CONSOLE.txt", "CONSOLE.txt"),
+            ("", "untitled"),
+            (" . ", "untitled"),
+            ("Été à Paris", "Été à Paris"),
+        ];
+
+        for (name, expected) in cases {
+            assert_eq!(sanitize_file_name(name), expected, "{name:?}");
+        }
+    }
+
+    #[test]
+    fn neither_photo_nor_video_is_other() {
+        assert!(unknown_item().media_type == MediaType::Other);
+    }
+
+    #[test]
+    fn other_is_downloaded_as_is() {
+        let url = download_url(&unknown_item(), Quality::MaxDimension(2048));
+
+        assert_eq!(url, "https://example.com/unknown=d");
+    }
+
+    #[tokio::test]
+    async fn other_is_skipped() {
+        let outcome = download_unknown(UnknownMedia::Skip).await;
+
+        assert!(matches!(outcome, Ok(Outcome::Skipped)));
+    }
+
+    #[tokio::test]
+    async fn other_fails() {
+        let error = download_unknown(UnknownMedia::Error).await.err().unwrap();
+
+        assert_eq!(
+            error.to_string(),
+            "unknown.xyz (unknown) is neither a photo nor a video"
+        );
+    }
 }
@@ -1,146 +1,1179 @@
-use anyhow::{anyhow, Error, Result};
-use api::{Api, Id, MediaItemResponse, MediaItemSearchRequest};
+use anyhow::{bail, Context, Error, Result};
+use api::{
+    Api, ContentCategory, ContentFilter, DateFilter, Feature, FeatureFilter, Filters, Id,
+    MediaItemIdsResponse, MediaItemResponse, MediaItemSearchRequest, Order, BATCH_GET_SIZE,
+    MEDIA_ITEMS_PAGE_SIZE, MEDIA_ITEM_IDS_FIELDS,
+};
 use args::Cli;
-use clap::StructOpt;
+use audit::AuditLog;
+use chrono::{NaiveDate, Utc};
+use clap::{CommandFactory, StructOpt};
 use client::get_api;
-use config::{configure, does_config_exist, Configuration, LocalAlbum};
+#[cfg(feature = "interactive")]
+use config::configure;
+use config::{
+    add_album, does_config_exist, export_config, import_config, print_disk_usage, print_paths,
+    AlbumFilters, Configuration, SyncOutcome, SyncStatus,
+};
+#[cfg(feature = "interactive")]
+use dialoguer::Confirm;
 use directories::ProjectDirs;
-use futures::{stream, StreamExt, TryStreamExt};
-use item::{download_file, Item, MediaType};
-use std::fs::create_dir_all;
+use error::SyncError;
+use event::{emit, SyncEvent};
+use futures::{channel::mpsc, future, stream, SinkExt, Stream, StreamExt, TryStreamExt};
+use item::{download_file, reorganize_folder, DownloadOptions, Item, Outcome, Quality, Selection};
+use manifest::Manifest;
+use output::{info, warning};
+use progress::{Progress, Tally};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::create_dir_all,
+    future::Future,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use storage::{LocalStorage, StorageBackend};
+use syncignore::{SyncIgnore, SYNCIGNORE_FILE};
+#[cfg(feature = "interactive")]
+use theme::ThemeChoice;
 
 mod album;
 mod api;
 mod args;
+mod audit;
 mod client;
 mod config;
+mod error;
+mod event;
+mod index;
 mod item;
+mod manifest;
+#[cfg(feature = "interactive")]
+mod menu;
+mod output;
+mod progress;
+#[cfg(feature = "interactive")]
+mod purge;
+mod storage;
+mod syncignore;
+mod theme;
+mod verify;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
+    output::set_quiet(cli.quiet);
+    if let Some(user_agent) = &cli.user_agent {
+        client::set_user_agent(user_agent.clone());
+    }
+    if let Some(credentials) = &cli.credentials {
+        client::set_credentials(credentials.clone());
+    }
+    if cli.force_reauth {
+        client::set_force_reauth();
+    }
+    #[cfg(feature = "interactive")]
+    if let ThemeChoice::Simple = cli.theme() {
+        console::set_colors_enabled(false);
+        console::set_colors_enabled_stderr(false);
+    }
+    if let Some(shell) = cli.generate_completions {
+        clap_complete::generate(
+            shell,
+            &mut Cli::command(),
+            env!("CARGO_PKG_NAME"),
+            &mut std::io::stdout(),
+        );
+        return Ok(());
+    }
+    if cli.generate_man {
+        clap_mangen::Man::new(Cli::command()).render(&mut std::io::stdout())?;
+        return Ok(());
+    }
+
     let project_dirs = ProjectDirs::from("app", "Redwarp", "Sync Google Photo")
         .expect("Couldn't create a project dir");
 
     let should_configure = if cli.configure {
         true
     } else {
-        !does_config_exist(&project_dirs)
+        // The library and explicit albums don't need any album to be configured.
+        !cli.all_library && cli.album_id.is_none() && !does_config_exist(&project_dirs)
     };
 
-    if should_configure {
-        configure(&project_dirs).await?;
+    if cli.paths {
+        print_paths(&project_dirs)?;
+    } else if cli.du {
+        print_disk_usage(&project_dirs)?;
+    } else if let Some(file) = &cli.export_config {
+        export_config(&project_dirs, file)?;
+    } else if let Some(file) = &cli.import_config {
+        import_config(&project_dirs, file, &cli)?;
+    } else if cli.check_auth {
+        client::check_auth().await?;
+        println!("Authentication works");
+    } else if cli.add_album {
+        add_album(&project_dirs, &cli).await?;
+    } else if cli.list_albums {
+        album::print_albums(get_api().await?).await?;
+    } else if cli.search {
+        search(&cli).await?;
+    } else if cli.verify || cli.repair {
+        verify::verify(&project_dirs, &cli, cli.repair).await?;
+    } else if cli.reorganize {
+        reorganize(&project_dirs)?;
+    } else if cli.purge {
+        #[cfg(feature = "interactive")]
+        purge::purge(&project_dirs, cli.theme().theme().as_ref())?;
+        #[cfg(not(feature = "interactive"))]
+        bail!("--purge asks for confirmation, this build has no menus");
+    } else if should_configure {
+        if !cli.configure && !theme::is_interactive() {
+            // Rather than hanging a cron job on a menu nobody will ever see.
+            bail!("No configuration found; run interactively or pass --album-id");
+        }
+        #[cfg(feature = "interactive")]
+        configure(&project_dirs, &cli).await?;
+        #[cfg(not(feature = "interactive"))]
+        bail!("No configuration found and this build has no menus; pass --album-id or --add-album");
     } else {
         // dostuff().await?;
-        synchronize(&project_dirs).await?;
+        synchronize(&project_dirs, &cli).await?;
     }
 
     Ok(())
 }
 
-#[derive(Default)]
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+/// How long to wait before the first retry of a download, doubled for each of the next ones.
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
 struct Page {
     items: Vec<Item>,
     next_page_token: Option<String>,
 }
 
-impl Extend<Page> for Page {
-    fn extend<T: IntoIterator<Item = Page>>(&mut self, iter: T) {
-        for page in iter {
-            self.items.extend(page.items)
+impl From<MediaItemResponse> for Page {
+    /// A page without items comes without `media_items` at all.
+    fn from(response: MediaItemResponse) -> Self {
+        Self {
+            items: response
+                .media_items
+                .unwrap_or_default()
+                .into_iter()
+                .map(Item::from_media_item)
+                .collect(),
+            next_page_token: response.next_page_token,
         }
     }
 }
 
-async fn get_next_page(api: &Api, album_id: &Id, next_page_token: Option<String>) -> Result<Page> {
+/// What to search for on Google's side.
+#[derive(Clone, Copy)]
+enum Source<'a> {
+    Album(&'a Id),
+    Library(&'a Filters, Option<Order>),
+    /// Items already known by their id, fetched [`BATCH_GET_SIZE`] at a time.
+    Ids(&'a [Id]),
+}
+
+/// The search for a page of the source, which can't be of ids.
+fn search_request(source: Source<'_>, page_token: Option<String>) -> MediaItemSearchRequest<'_> {
+    let (album_id, filters, order) = match source {
+        Source::Album(album_id) => (Some(album_id), None, None),
+        Source::Library(filters, order) => (
+            None,
+            Some(filters).filter(|filters| !filters.is_empty()),
+            order,
+        ),
+        Source::Ids(_) => unreachable!("Ids are fetched, not searched"),
+    };
+
+    MediaItemSearchRequest {
+        album_id,
+        page_size: Some(MEDIA_ITEMS_PAGE_SIZE),
+        page_token,
+        filters,
+        order_by: order.map(Order::order_by),
+    }
+}
+
+async fn get_next_page(
+    api: &Api,
+    source: Source<'_>,
+    next_page_token: Option<String>,
+) -> Result<Page> {
+    if let Source::Ids(ids) = source {
+        // The page token is the offset of the page in the ids.
+        let offset = next_page_token.map_or(Ok(0), |token| token.parse())?;
+        let end = ids.len().min(offset + BATCH_GET_SIZE);
+        let items = api
+            .batch_get(&ids[offset..end])
+            .await?
+            .into_iter()
+            .map(Item::from_media_item)
+            .collect();
+        return Ok(Page {
+            items,
+            next_page_token: (end < ids.len()).then(|| end.to_string()),
+        });
+    }
+
     let media_response: MediaItemResponse = api
         .post(
             "https://photoslibrary.googleapis.com/v1/mediaItems:search",
-            &MediaItemSearchRequest {
-                album_id,
-                page_size: Some(50),
-                page_token: next_page_token,
-            },
+            &search_request(source, next_page_token),
         )
         .await?;
 
-    let items = if let Some(media_items) = media_response.media_items {
-        media_items
-            .into_iter()
-            .filter_map(|item| {
-                let media_type = if item.media_metadata.photo.is_some() {
-                    MediaType::Photo
-                } else if item.media_metadata.video.is_some() {
-                    MediaType::Video
-                } else {
-                    return None;
-                };
-
-                Some(Item::new(item.filename, item.base_url, media_type))
-            })
-            .collect()
-    } else {
-        vec![]
-    };
+    Ok(Page::from(media_response))
+}
 
-    Ok(Page {
-        items,
-        next_page_token: media_response.next_page_token,
-    })
+/// Streams the items of the source, see [`paginate`].
+fn list_items<'a>(
+    api: &'a Api,
+    source: Source<'a>,
+    page_token: &'a Mutex<Option<String>>,
+) -> impl Stream<Item = Result<Item>> + 'a {
+    paginate(page_token, move |token| get_next_page(api, source, token))
 }
 
-async fn download_all(api: &Api, local_album: &LocalAlbum) -> Result<()> {
+/// Streams the items of the pages returned by `fetch_page`, given the token of each page.
+///
+/// The next page is only requested once every item of the current one has been consumed,
+/// so at most one page is held in memory, however large the album is.
+///
+/// The listing starts from `page_token` if it is set, and keeps it updated with the token of the
+/// page being streamed, to resume from later. Should Google reject it, the listing starts over.
+fn paginate<'a, F, Fut>(
+    page_token: &'a Mutex<Option<String>>,
+    fetch_page: F,
+) -> impl Stream<Item = Result<Item>> + 'a
+where
+    F: Fn(Option<String>) -> Fut + 'a,
+    Fut: Future<Output = Result<Page>> + 'a,
+{
     enum Paging {
         Starting,
         Next(String),
         Finish,
     }
 
-    let stream = stream::try_unfold(Paging::Starting, |token| async {
-        match token {
-            Paging::Starting => {
-                let page = get_next_page(api, &local_album.album_id, None).await?;
-                let next = match &page.next_page_token {
-                    Some(token) => Paging::Next(token.clone()),
-                    None => Paging::Finish,
-                };
-                Ok::<_, Error>(Some((page, next)))
+    let stream = stream::try_unfold(
+        (Paging::Starting, fetch_page),
+        move |(paging, fetch_page)| async move {
+            let page = match paging {
+                Paging::Starting => {
+                    let resume_token = page_token.lock().unwrap().clone();
+                    match fetch_page(resume_token.clone()).await {
+                        Err(e) if resume_token.is_some() => {
+                            warning!(
+                                "couldn't resume the last synchronization, starting over: {e}"
+                            );
+                            *page_token.lock().unwrap() = None;
+                            fetch_page(None).await?
+                        }
+                        result => result?,
+                    }
+                }
+                Paging::Next(next_page_token) => {
+                    let page = fetch_page(Some(next_page_token.clone())).await?;
+                    *page_token.lock().unwrap() = Some(next_page_token);
+                    page
+                }
+                Paging::Finish => return Ok(None),
+            };
+
+            // An empty page may still have a token, the listing goes on. An empty token though
+            // would start it over.
+            let next = match &page.next_page_token {
+                Some(token) if !token.is_empty() => Paging::Next(token.clone()),
+                _ => Paging::Finish,
+            };
+            Ok::<_, Error>(Some((page, (next, fetch_page))))
+        },
+    );
+
+    stream
+        .map_ok(|page| stream::iter(page.items.into_iter().map(Ok)))
+        .try_flatten()
+}
+
+/// The ids of the items of the source that aren't in the folder at `path` yet, listing nothing
+/// but the ids to be quick.
+async fn missing_ids(api: &Api, source: Source<'_>, path: &Path) -> Result<Vec<Id>> {
+    let manifest = Manifest::load(path)?;
+    let storage = LocalStorage::new(path);
+    let is_downloaded = |id: &Id| {
+        manifest
+            .items
+            .get(id.as_str())
+            .is_some_and(|file| storage.exists(file))
+    };
+
+    let mut missing_ids = vec![];
+    let mut page_token = None;
+    loop {
+        let response: MediaItemIdsResponse = api
+            .post(
+                &format!(
+                    "https://photoslibrary.googleapis.com/v1/mediaItems:search?fields={MEDIA_ITEM_IDS_FIELDS}"
+                ),
+                &search_request(source, page_token),
+            )
+            .await?;
+        missing_ids.extend(
+            response
+                .media_items
+                .unwrap_or_default()
+                .into_iter()
+                .map(|media_item| media_item.id)
+                .filter(|id| !is_downloaded(id)),
+        );
+
+        match response.next_page_token {
+            Some(next_page_token) if !next_page_token.is_empty() => {
+                page_token = Some(next_page_token)
             }
-            Paging::Next(next_page_token) => {
-                let page = get_next_page(api, &local_album.album_id, Some(next_page_token)).await?;
-                let next = match &page.next_page_token {
-                    Some(token) => Paging::Next(token.clone()),
-                    None => Paging::Finish,
+            _ => return Ok(missing_ids),
+        }
+    }
+}
+
+/// Sends the listed `items` to the downloads, with their position and the token of their page,
+/// until the end of `selection`'s range or until the downloads stop.
+async fn list_ahead(
+    items: impl Stream<Item = Result<Item>>,
+    listing_token: &Mutex<Option<String>>,
+    selection: &Selection,
+    mut sender: mpsc::Sender<(Option<String>, usize, Item)>,
+) -> Result<()> {
+    tokio::pin!(items);
+    let mut position = 0;
+    while let Some(item) = items.try_next().await? {
+        position += 1;
+        if selection.is_past_range(position) {
+            break;
+        }
+        // The token of the page the item comes from.
+        let token = listing_token.lock().unwrap().clone();
+        if sender.send((token, position, item)).await.is_err() {
+            // The downloads stopped, no need to list any further.
+            break;
+        }
+    }
+    if let Some(range) = selection
+        .range
+        .as_ref()
+        .filter(|range| position < *range.start())
+    {
+        warning!(
+            "only {position} items listed, none in the range {}-{}",
+            range.start(),
+            range.end()
+        );
+    }
+
+    Ok(())
+}
+
+/// Downloads every item of the source into `path`, returning how many were downloaded and how
+/// many failed.
+///
+/// A failed item is reported and the others carry on.
+///
+/// Only the items matching `selection` are downloaded, up to `options.max_items`. Once
+/// `interrupted` is set, no new download is started, but the ones in flight are allowed to finish
+/// so that no temporary file is left behind.
+///
+/// The listing runs up to a page ahead of the downloads, so that the next page is fetched while
+/// the items of the current one download.
+///
+/// `page_token` is where the listing starts from and stops at, see [`list_items`]. It follows the
+/// downloads rather than the listing, not to skip the items listed ahead on a resume.
+///
+/// Items are numbered in the order Google lists them, from where the listing starts, for
+/// [`DownloadOptions::sequence_prefix`].
+async fn download_all(
+    api: &Api,
+    source: Source<'_>,
+    page_token: &Mutex<Option<String>>,
+    selection: &Selection,
+    path: &Path,
+    options: &DownloadOptions,
+    interrupted: &AtomicBool,
+) -> Result<Tally> {
+    let (sender, receiver) = mpsc::channel(MEDIA_ITEMS_PAGE_SIZE as usize);
+    let listing_token = Mutex::new(page_token.lock().unwrap().clone());
+    let listing_token = &listing_token;
+    let items = paginate(listing_token, move |token| async move {
+        let mut page = get_next_page(api, source, token).await?;
+        if options.newest_first {
+            // Page by page, not to hold the whole album. The undated items go last.
+            page.items
+                .sort_by(|a, b| b.creation_time().cmp(&a.creation_time()));
+        }
+        Ok(page)
+    });
+    let listing = list_ahead(items, listing_token, selection, sender);
+
+    // Known up front for albums, rather than after listing them.
+    let total_items = match source {
+        Source::Ids(ids) => Some(ids.len()),
+        Source::Album(album_id) => api
+            .get_album(album_id)
+            .await
+            .ok()
+            .map(|album| album.media_items_count()),
+        Source::Library(..) => None,
+    };
+    let manifest = Mutex::new(Manifest::load(path)?);
+    let storage = &LocalStorage::new(path);
+    // A resumed listing starts after the items downloaded before the interruption, they are done.
+    let is_resuming = page_token.lock().unwrap().is_some();
+    let present = match is_resuming {
+        true => manifest
+            .lock()
+            .unwrap()
+            .items
+            .values()
+            .filter(|file| storage.exists(file))
+            .count(),
+        false => 0,
+    };
+    let progress = Progress::new(total_items).with_present(present);
+    let progress_ref = &progress;
+    let manifest_ref = &manifest;
+    let syncignore = &SyncIgnore::load(path)?;
+
+    let is_full = |count| {
+        options
+            .max_items
+            .is_some_and(|max_items| count >= max_items)
+    };
+    let is_full = &is_full;
+    // Counts the downloads in flight as well, so that no more than `max_items` are started.
+    let started = AtomicUsize::new(0);
+    let started = &started;
+
+    let downloads = receiver
+        .take_while(|_| {
+            future::ready(!interrupted.load(Ordering::SeqCst) && !is_full(progress.items()))
+        })
+        .map(|(token, position, item)| {
+            *page_token.lock().unwrap() = token;
+            (position, item)
+        })
+        .filter(|(position, item)| {
+            future::ready(selection.is_in_range(*position) && selection.matches(item))
+        })
+        .for_each_concurrent(4, |(position, item)| async move {
+            if syncignore.is_ignored(item.filename()) {
+                let filename = item.filename().to_string();
+                let reason = format!("it matches the {SYNCIGNORE_FILE}");
+                emit(&options.events, SyncEvent::Skipped { filename, reason });
+                return;
+            }
+            if is_full(started.fetch_add(1, Ordering::SeqCst)) {
+                started.fetch_sub(1, Ordering::SeqCst);
+                return;
+            }
+
+            let mut retries = 0;
+            let outcome = loop {
+                match download_file(&item, position, storage, options, progress_ref).await {
+                    Err(e) if retries < options.max_retries && is_retryable(&e) => {
+                        tokio::time::sleep(RETRY_DELAY * 2u32.pow(retries)).await;
+                        retries += 1;
+                        progress_ref.item_retried();
+                    }
+                    outcome => break outcome,
+                }
+            }
+            .with_context(|| format!("Couldn't download {} ({})", item.filename(), item.id()));
+            if let Some(audit) = &options.audit {
+                audit.record(&item, path, &outcome);
+            }
+            match outcome {
+                Ok(Outcome::Downloaded { file, companions }) => {
+                    manifest_ref
+                        .lock()
+                        .unwrap()
+                        .insert(item.id().to_string(), file, companions);
+                    progress_ref.item_done();
+                }
+                Ok(Outcome::Skipped) => {
+                    started.fetch_sub(1, Ordering::SeqCst);
+                }
+                Err(e) => {
+                    started.fetch_sub(1, Ordering::SeqCst);
+                    progress_ref.item_failed();
+                    if retries > 0 && retries == options.max_retries {
+                        progress_ref.item_exhausted();
+                    }
+                    let error = format!("{e:#}");
+                    emit(&options.events, SyncEvent::Failed { error });
+                }
+            }
+        });
+    // Should the listing fail, the channel closes and the downloads in flight finish first.
+    let work = future::join(listing, downloads);
+    tokio::pin!(work);
+
+    let mut report = tokio::time::interval(REPORT_INTERVAL);
+    // The first tick completes immediately, nothing to report yet.
+    report.tick().await;
+    loop {
+        tokio::select! {
+            (result, ()) = &mut work => {
+                manifest.lock().unwrap().save(path)?;
+                result?;
+                break;
+            }
+            _ = report.tick() => {
+                info!("{}", progress.report());
+                // Saved along the way, not to lose track of everything on a crash.
+                manifest.lock().unwrap().save(path)?;
+            }
+        }
+    }
+
+    if interrupted.load(Ordering::SeqCst) {
+        if let Some(remaining) = progress.remaining() {
+            info!("{remaining} items left for the next run");
+        }
+    }
+
+    Ok(progress.tally())
+}
+
+fn filters(cli: &Cli) -> Filters {
+    Filters {
+        include_archived_media: cli.include_archived,
+        feature_filter: cli.favorites_only.then(|| FeatureFilter {
+            included_features: vec![Feature::Favorites],
+        }),
+        content_filter: (!cli.categories.is_empty()).then(|| ContentFilter {
+            included_content_categories: cli.categories.clone(),
+        }),
+        // Google only sorts the items when filtering them by date.
+        date_filter: (cli.from.is_some() || cli.to.is_some() || cli.order.is_some())
+            .then(|| DateFilter::between(cli.from, cli.to)),
+    }
+}
+
+fn selection(cli: &Cli) -> Selection {
+    Selection {
+        since: None,
+        until: None,
+        media_type: None,
+        ids: None,
+        excluded_extensions: cli
+            .excluded_extensions
+            .iter()
+            .map(|ext| ext.trim_start_matches('.').to_lowercase())
+            .collect(),
+        min_resolution: cli.min_resolution,
+        range: cli.range.clone(),
+    }
+}
+
+/// Whether the error may not happen again, like a dropped connection.
+fn is_retryable(error: &Error) -> bool {
+    matches!(
+        error.downcast_ref(),
+        Some(SyncError::Network(_) | SyncError::RateLimited(_) | SyncError::TimedOut(_))
+    )
+}
+
+/// Whether Google answered that the album or item doesn't exist.
+fn is_not_found(error: &Error) -> bool {
+    matches!(error.downcast_ref(), Some(SyncError::NotFound(_)))
+}
+
+/// Offers to remove the albums deleted on Google from the configuration, their folders are kept.
+#[cfg(feature = "interactive")]
+fn remove_albums(
+    configuration: &mut Configuration,
+    project_dirs: &ProjectDirs,
+    cli: &Cli,
+    mut indices: Vec<usize>,
+) -> Result<()> {
+    let theme = cli.theme().theme();
+    // From the last one, not to shift the indices still to remove.
+    indices.sort_unstable_by(|a, b| b.cmp(a));
+    for index in indices {
+        let should_remove = Confirm::with_theme(theme.as_ref())
+            .with_prompt(format!(
+                "Stop synchronizing {}?",
+                configuration.local_albums[index].name
+            ))
+            .default(false)
+            .interact()?;
+        if should_remove {
+            configuration.local_albums.remove(index);
+        }
+    }
+
+    configuration.save(project_dirs)
+}
+
+/// The items of the library in some categories, created between two days, keyed by those.
+type CategoryIds =
+    HashMap<(Vec<ContentCategory>, Option<NaiveDate>, Option<NaiveDate>), Arc<HashSet<String>>>;
+
+/// Narrows `selection` down with the filters of an album.
+///
+/// Google refuses filters along with an album id, so they are applied locally. The categories of
+/// an item aren't listed though: the library is searched for them instead, keeping the album
+/// items that come up. The search is bounded by the dates of the album, and kept in `searches`
+/// for the other albums of the run with the same filters.
+async fn album_selection(
+    api: &Api,
+    filters: &AlbumFilters,
+    selection: Selection,
+    searches: &mut CategoryIds,
+) -> Result<Selection> {
+    let since = selection.since.max(filters.from);
+    let ids = if filters.categories.is_empty() {
+        None
+    } else {
+        let key = (filters.categories.clone(), since, filters.to);
+        match searches.get(&key) {
+            Some(ids) => Some(ids.clone()),
+            None => {
+                let library_filters = Filters {
+                    content_filter: Some(ContentFilter {
+                        included_content_categories: filters.categories.clone(),
+                    }),
+                    date_filter: (since.is_some() || filters.to.is_some())
+                        .then(|| DateFilter::between(since, filters.to)),
+                    ..Default::default()
                 };
-                Ok(Some((page, next)))
+                let ids: Arc<HashSet<_>> = Arc::new(
+                    list_items(
+                        api,
+                        Source::Library(&library_filters, None),
+                        &Mutex::new(None),
+                    )
+                    .map_ok(|item| item.id().to_string())
+                    .try_collect()
+                    .await?,
+                );
+                searches.insert(key, ids.clone());
+                Some(ids)
             }
-            Paging::Finish => Ok(None),
         }
-    });
+    };
 
-    let items = stream.flat_map(|page_result: Result<_, _>| match page_result {
-        Ok(page) => stream::iter(page.items.into_iter().map(Ok).collect::<Vec<_>>()),
-        _ => stream::iter(vec![Err(anyhow!("Error with page"))]),
-    });
+    Ok(Selection {
+        since,
+        until: filters.to,
+        media_type: filters.media_type,
+        ids,
+        ..selection
+    })
+}
 
-    items
-        .try_for_each_concurrent(4, |item| async move {
-            download_file(&item, &local_album.path).await
+/// Prints the items of the library matching the filters, without downloading them.
+async fn search(cli: &Cli) -> Result<()> {
+    let api = get_api().await?;
+    let filters = filters(cli);
+    let selection = selection(cli);
+
+    let mut count = 0;
+    list_items(api, Source::Library(&filters, cli.order), &Mutex::new(None))
+        .try_filter(|item| future::ready(selection.matches(item)))
+        .try_for_each(|item| {
+            println!("{} {}", item.id(), item.filename());
+            count += 1;
+            future::ready(Ok(()))
         })
         .await?;
+    println!("{count} items found");
 
     Ok(())
 }
 
-async fn synchronize(project_dirs: &ProjectDirs) -> Result<()> {
+/// Renames the files of every synchronized folder to match the current naming scheme.
+fn reorganize(project_dirs: &ProjectDirs) -> Result<()> {
     let configuration = Configuration::load(project_dirs)?;
+    let folders = configuration
+        .local_albums
+        .iter()
+        .map(|local_album| configuration.album_path(local_album))
+        .chain(std::iter::once(configuration.library_path()));
+
+    let mut renamed = 0;
+    for folder in folders.filter(|folder| folder.is_dir()) {
+        renamed += reorganize_folder(folder)?;
+    }
+    info!("{renamed} files renamed");
+
+    Ok(())
+}
+
+/// Writes the files asked for next to the media of a synchronized folder.
+fn write_extras(cli: &Cli, path: &Path, title: &str) -> Result<()> {
+    if cli.write_index {
+        index::write_index(path, title)?;
+    }
+    if cli.write_nomedia {
+        index::write_nomedia(path)?;
+    }
+
+    Ok(())
+}
+
+async fn synchronize(project_dirs: &ProjectDirs, cli: &Cli) -> Result<()> {
+    let mut configuration = Configuration::load(project_dirs)?;
     let api = get_api().await?;
 
-    for local_album in &configuration.local_albums {
-        println!("Synchronizing {}", local_album.name);
-        create_dir_all(&local_album.path)?;
-        download_all(api, local_album).await?;
+    let (events, printer) = event::print_events();
+    let options = DownloadOptions {
+        write_metadata: cli.write_metadata,
+        max_size: cli.max_size,
+        verify_dimensions: cli.verify_dimensions,
+        folder_template: cli.folder_template.clone(),
+        max_items: cli.max_items,
+        sequence_prefix: cli.sequence_prefix,
+        // The albums can choose another one.
+        quality: Quality::Original,
+        convert_heic: cli.convert_heic,
+        keep_heic: cli.keep_heic,
+        live_photos: cli.live_photos,
+        max_retries: cli.max_retries,
+        unknown_media: cli.unknown_media,
+        item_timeout: cli.timeout_per_item,
+        // Google sorts the library, only the albums need it.
+        newest_first: false,
+        events: Some(events),
+        audit: Some(Arc::new(AuditLog::open(project_dirs)?)),
+    };
+    let filters = filters(cli);
+    let selection = selection(cli);
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    tokio::spawn({
+        let interrupted = interrupted.clone();
+        async move {
+            let mut pressed = false;
+            while tokio::signal::ctrl_c().await.is_ok() {
+                if pressed {
+                    // The downloads in flight leave their temporary files behind.
+                    eprintln!("Interrupted again, quitting now");
+                    std::process::exit(130);
+                }
+                pressed = true;
+                info!("Interrupted, waiting for the current downloads to finish");
+                info!("Press Ctrl+C again to quit now");
+                interrupted.store(true, Ordering::SeqCst);
+            }
+        }
+    });
+    if let Some(deadline) = cli.deadline {
+        // Stops like an interruption, the albums resume where they stopped on the next run.
+        let interrupted = interrupted.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(deadline).await;
+            info!("Deadline reached, waiting for the current downloads to finish");
+            interrupted.store(true, Ordering::SeqCst);
+        });
+    }
+
+    let mut tally = Tally::default();
+    if cli.all_library {
+        let path = configuration.library_path();
+        let since = configuration
+            .library_last_sync
+            .filter(|_| cli.since_last_run)
+            .map(|last_sync| last_sync.date_naive());
+        // Google sorts the library, unlike the albums.
+        let order = cli.order.or(cli.newest_first.then_some(Order::Newest));
+        let filters = Filters {
+            // Google only sorts the items when filtering them by date.
+            date_filter: since
+                .map(DateFilter::since)
+                .or(filters.date_filter)
+                .or_else(|| order.map(|_| DateFilter::between(None, None))),
+            ..filters
+        };
+
+        info!("Synchronizing the library");
+        create_dir_all(&path)?;
+        let started_at = Utc::now();
+        let source = Source::Library(&filters, order);
+        let result = async {
+            let ids = match cli.download_missing_only {
+                true => Some(missing_ids(api, source, &path).await?),
+                false => None,
+            };
+            download_all(
+                api,
+                ids.as_deref().map_or(source, Source::Ids),
+                &Mutex::new(None),
+                &Selection {
+                    since,
+                    ..selection.clone()
+                },
+                &path,
+                &options,
+                &interrupted,
+            )
+            .await
+        }
+        .await;
+
+        // Part of the library was synchronized at most, it stays as it was.
+        if cli.range.is_none() {
+            let status = SyncStatus::new(
+                started_at,
+                &result,
+                interrupted.load(Ordering::SeqCst),
+                options.max_items,
+            );
+            if status.outcome == SyncOutcome::Succeeded {
+                configuration.library_last_sync = Some(started_at);
+            }
+            configuration.library_status = Some(status);
+            configuration.save(project_dirs)?;
+        }
+        tally += result?;
+        write_extras(cli, &path, "Library")?;
+    } else {
+        if !filters.is_empty() {
+            warning!("Google doesn't support filters on albums, ignoring them");
+        }
+        if cli.order.is_some() {
+            warning!("albums keep the order they have on Google, ignoring --order");
+        }
+
+        let indices = match &cli.album_id {
+            Some(album_id) => vec![configuration.album_index(album_id)?],
+            None => (0..configuration.local_albums.len()).collect(),
+        };
+
+        let mut deleted_albums = vec![];
+        let mut searches = CategoryIds::new();
+        for index in indices {
+            if interrupted.load(Ordering::SeqCst) {
+                break;
+            }
+            let local_album = &configuration.local_albums[index];
+            // Albums can't be filtered by date on Google's side, the items are skipped locally.
+            let since = local_album
+                .last_sync
+                .filter(|_| cli.since_last_run)
+                .map(|last_sync| last_sync.date_naive());
+
+            // Positions are counted from the first page, a resumed listing would get them wrong.
+            // Listing the missing items is a resume of its own, and another folder starts over.
+            let resume_page_token = local_album.resume_page_token.clone().filter(|_| {
+                !cli.sequence_prefix
+                    && !cli.newest_first
+                    && cli.range.is_none()
+                    && !cli.download_missing_only
+                    && cli.output.is_none()
+            });
+            if resume_page_token.is_some() {
+                info!("Resuming {} where it stopped", local_album.name);
+            } else {
+                info!("Synchronizing {}", local_album.name);
+            }
+            let path = match &cli.output {
+                Some(output) => output.clone(),
+                None => configuration.album_path(local_album),
+            };
+            create_dir_all(&path)?;
+            if cli.download_cover {
+                if let Err(e) = album::download_cover(api, &local_album.album_id, &path).await {
+                    warning!("couldn't download the cover of {}: {e}", local_album.name);
+                }
+            }
+            let mut selection = Selection {
+                since,
+                ..selection.clone()
+            };
+            if let Some(filters) = &local_album.filters {
+                selection = album_selection(api, filters, selection, &mut searches).await?;
+            }
+            let options = DownloadOptions {
+                quality: local_album.quality.unwrap_or_default(),
+                newest_first: cli.newest_first,
+                ..options.clone()
+            };
+            let started_at = Utc::now();
+            let page_token = Mutex::new(resume_page_token);
+            let source = Source::Album(&local_album.album_id);
+            let result = async {
+                let ids = match cli.download_missing_only {
+                    true => Some(missing_ids(api, source, &path).await?),
+                    false => None,
+                };
+                download_all(
+                    api,
+                    ids.as_deref().map_or(source, Source::Ids),
+                    &page_token,
+                    &selection,
+                    &path,
+                    &options,
+                    &interrupted,
+                )
+                .await
+            }
+            .await;
+
+            if result.as_ref().is_err_and(is_not_found) {
+                let name = &configuration.local_albums[index].name;
+                warning!("Album '{name}' no longer exists on Google");
+                deleted_albums.push(index);
+                continue;
+            }
+            if cli.output.is_some() || cli.range.is_some() {
+                // The configured folder wasn't synchronized, or only part of the album, the
+                // configuration stays as it was.
+                tally += result?;
+                write_extras(cli, &path, &configuration.local_albums[index].name)?;
+                continue;
+            }
+
+            let local_album = &mut configuration.local_albums[index];
+            let status = SyncStatus::new(
+                started_at,
+                &result,
+                interrupted.load(Ordering::SeqCst),
+                options.max_items,
+            );
+            if status.outcome == SyncOutcome::Succeeded {
+                local_album.last_sync = Some(started_at);
+                local_album.resume_page_token = None;
+            } else if !cli.download_missing_only {
+                // Only what was stopped resumes, the items that failed on the pages before must
+                // be listed again.
+                let resumes = result.is_err()
+                    || matches!(
+                        status.outcome,
+                        SyncOutcome::Interrupted | SyncOutcome::Incomplete
+                    );
+                local_album.resume_page_token =
+                    page_token.into_inner().unwrap().filter(|_| resumes);
+            }
+            local_album.last_status = Some(status);
+            configuration.save(project_dirs)?;
+            tally += result?;
+            write_extras(cli, &path, &configuration.local_albums[index].name)?;
+        }
+
+        #[cfg(feature = "interactive")]
+        if !deleted_albums.is_empty() && theme::is_interactive() {
+            remove_albums(&mut configuration, project_dirs, cli, deleted_albums)?;
+        }
+    }
+
+    // Lets the last events be printed before the summary.
+    drop(options);
+    printer.await?;
+
+    let downloaded = tally.downloaded;
+    if interrupted.load(Ordering::SeqCst) {
+        info!("Synchronization interrupted, {downloaded} items downloaded");
+    } else {
+        info!("Synchronization done, {downloaded} items downloaded");
+    }
+    if tally.retries > 0 {
+        info!(
+            "{} retries, {} items still failing after {} retries",
+            tally.retries, tally.exhausted, cli.max_retries
+        );
+    }
+    if tally.failed > 0 {
+        bail!("{} items couldn't be downloaded", tally.failed);
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A page of Google's response, with an item for each id.
+    fn response(ids: Option<&[&str]>, next_page_token: Option<&str>) -> MediaItemResponse {
+        let media_items = ids.map(|ids| {
+            ids.iter()
+                .map(|id| {
+                    json!({
+                        "id": id,
+                        "filename": format!("{id}.jpg"),
+                        "baseUrl": format!("https://example.com/{id}"),
+                        "productUrl": format!("https://photos.google.com/{id}"),
+                        "mediaMetadata": { "photo": {} },
+                    })
+                })
+                .collect::<Vec<_>>()
+        });
+        serde_json::from_value(json!({
+            "mediaItems": media_items,
+            "nextPageToken": next_page_token,
+        }))
+        .unwrap()
+    }
+
+    /// Serves canned pages by token, the first one without.
+    fn fetcher(
+        pages: Vec<(Option<&'static str>, MediaItemResponse)>,
+    ) -> impl Fn(Option<String>) -> future::Ready<Result<Page>> {
+        let pages = Mutex::new(pages);
+        move |token| {
+            let mut pages = pages.lock().unwrap();
+            let index = pages
+                .iter()
+                .position(|(page_token, _)| page_token.map(str::to_string) == token)
+                .unwrap_or_else(|| panic!("unexpected page token {token:?}"));
+            future::ready(Ok(Page::from(pages.remove(index).1)))
+        }
+    }
+
+    async fn list(
+        fetch_page: impl Fn(Option<String>) -> future::Ready<Result<Page>>,
+    ) -> Vec<String> {
+        let page_token = Mutex::new(None);
+        paginate(&page_token, fetch_page)
+            .map_ok(|item| item.id().to_string())
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn paginate_yields_every_item_once() {
+        let ids = list(fetcher(vec![
+            (None, response(Some(&["a", "b"]), Some("2"))),
+            (Some("2"), response(Some(&["c"]), Some("3"))),
+            (Some("3"), response(Some(&["d", "e"]), None)),
+        ]))
+        .await;
+
+        assert_eq!(ids, ["a", "b", "c", "d", "e"]);
+    }
+
+    #[tokio::test]
+    async fn paginate_stops_without_a_next_page_token() {
+        // The fetcher panics if asked for any other page.
+        let ids = list(fetcher(vec![(None, response(Some(&["a"]), None))])).await;
+
+        assert_eq!(ids, ["a"]);
+    }
+
+    /// Counts the pages fetched by `fetch_page`, each taking a moment like a request would.
+    fn delayed(
+        fetch_page: impl Fn(Option<String>) -> future::Ready<Result<Page>>,
+        fetched: Arc<AtomicUsize>,
+    ) -> impl Fn(Option<String>) -> future::BoxFuture<'static, Result<Page>> {
+        move |token| {
+            let page = fetch_page(token);
+            let fetched = fetched.clone();
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                fetched.fetch_add(1, Ordering::SeqCst);
+                page.await
+            })
+        }
+    }
+
+    fn three_pages() -> Vec<(Option<&'static str>, MediaItemResponse)> {
+        vec![
+            (None, response(Some(&["a", "b"]), Some("2"))),
+            (Some("2"), response(Some(&["c", "d"]), Some("3"))),
+            (Some("3"), response(Some(&["e"]), None)),
+        ]
+    }
+
+    #[tokio::test]
+    async fn paginate_holds_one_page_at_a_time() {
+        let fetched = Arc::new(AtomicUsize::new(0));
+        let page_token = Mutex::new(None);
+        let items = paginate(
+            &page_token,
+            delayed(fetcher(three_pages()), fetched.clone()),
+        );
+        tokio::pin!(items);
+
+        // The pages fetched once each item is consumed.
+        let mut fetched_by_item = vec![];
+        while let Some(item) = items.try_next().await.unwrap() {
+            fetched_by_item.push((item.id().to_string(), fetched.load(Ordering::SeqCst)));
+        }
+
+        let expected = [("a", 1), ("b", 1), ("c", 2), ("d", 2), ("e", 3)];
+        let expected: Vec<(String, usize)> = expected
+            .iter()
+            .map(|(id, fetched)| (id.to_string(), *fetched))
+            .collect();
+        assert_eq!(fetched_by_item, expected);
+    }
+
+    #[tokio::test]
+    async fn list_ahead_overlaps_the_downloads() {
+        let fetched = Arc::new(AtomicUsize::new(0));
+        let listing_token = Mutex::new(None);
+        let items = paginate(
+            &listing_token,
+            delayed(fetcher(three_pages()), fetched.clone()),
+        );
+        let (sender, receiver) = mpsc::channel(1);
+        let selection = Selection::default();
+        let listing = list_ahead(items, &listing_token, &selection, sender);
+        // Notes how many pages were listed as each item reaches the downloads.
+        let downloads = receiver
+            .map(|(_, position, _)| (position, fetched.load(Ordering::SeqCst)))
+            .collect::<Vec<_>>();
+
+        let (listed, downloaded) = future::join(listing, downloads).await;
+        listed.unwrap();
+
+        let positions: Vec<usize> = downloaded.iter().map(|(position, _)| *position).collect();
+        assert_eq!(positions, [1, 2, 3, 4, 5]);
+        // The first item was handed over while the next pages were still to be fetched.
+        assert_eq!(downloaded[0].1, 1);
+        assert_eq!(fetched.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn paginate_goes_on_after_an_empty_page_with_a_token() {
+        let ids = list(fetcher(vec![
+            (None, response(Some(&["a"]), Some("2"))),
+            (Some("2"), response(Some(&[]), Some("3"))),
+            (Some("3"), response(Some(&["b"]), None)),
+        ]))
+        .await;
+
+        assert_eq!(ids, ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn paginate_stops_on_an_empty_page_token() {
+        // Asking for the page of an empty token would start the listing over, the fetcher would
+        // panic.
+        let ids = list(fetcher(vec![(None, response(Some(&["a"]), Some("")))])).await;
+
+        assert_eq!(ids, ["a"]);
+    }
+
+    #[tokio::test]
+    async fn paginate_skips_a_page_without_items() {
+        let ids = list(fetcher(vec![
+            (None, response(Some(&["a"]), Some("2"))),
+            (Some("2"), response(None, Some("3"))),
+            (Some("3"), response(Some(&["b"]), None)),
+        ]))
+        .await;
+
+        assert_eq!(ids, ["a", "b"]);
+    }
+}
@@ -0,0 +1,129 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+/// Hidden in the folder it describes, so that it moves along with it.
+pub const MANIFEST_FILE: &str = ".sync-google-photo.json";
+
+/// The items downloaded into a folder, by id, with the path of their file relative to the folder.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Manifest {
+    pub items: BTreeMap<String, PathBuf>,
+    /// The media files downloaded along with an item, like the motion of a live photo or the HEIC
+    /// kept next to its JPEG, by id.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub companions: BTreeMap<String, Vec<PathBuf>>,
+}
+
+impl Manifest {
+    /// Loads the manifest of `folder`, empty if nothing was downloaded there yet.
+    pub fn load(folder: &Path) -> Result<Self> {
+        let manifest_file = folder.join(MANIFEST_FILE);
+        if manifest_file.exists() {
+            Ok(serde_json::from_reader(&File::open(manifest_file)?)?)
+        } else {
+            Ok(Manifest::default())
+        }
+    }
+
+    pub fn save(&self, folder: &Path) -> Result<()> {
+        fs::create_dir_all(folder)?;
+        serde_json::to_writer(&File::create(folder.join(MANIFEST_FILE))?, self)?;
+
+        Ok(())
+    }
+
+    /// Records the file of an item, along with its companions, replacing what was downloaded
+    /// before.
+    pub fn insert(&mut self, id: String, file: PathBuf, companions: Vec<PathBuf>) {
+        if companions.is_empty() {
+            self.companions.remove(&id);
+        } else {
+            self.companions.insert(id.clone(), companions);
+        }
+        self.items.insert(id, file);
+    }
+
+    /// The file of the item with this id, followed by its companions.
+    pub fn files(&self, id: &str) -> impl Iterator<Item = &PathBuf> {
+        self.items
+            .get(id)
+            .into_iter()
+            .chain(self.companions.get(id).into_iter().flatten())
+    }
+
+    /// Points the items and companions of the file at `from` to `to` instead, after a rename.
+    pub fn rename(&mut self, from: &Path, to: &Path) {
+        let paths = self
+            .items
+            .values_mut()
+            .chain(self.companions.values_mut().flatten());
+        for path in paths.filter(|path| *path == from) {
+            *path = to.to_path_buf();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_lists_the_companions_after_the_file() {
+        let mut manifest = Manifest::default();
+        manifest.insert(
+            "live".to_string(),
+            PathBuf::from("IMG_1.HEIC"),
+            vec![PathBuf::from("IMG_1.mov")],
+        );
+        manifest.insert("still".to_string(), PathBuf::from("IMG_2.jpg"), vec![]);
+
+        let files: Vec<&PathBuf> = manifest.files("live").collect();
+        assert_eq!(files, [Path::new("IMG_1.HEIC"), Path::new("IMG_1.mov")]);
+        let files: Vec<&PathBuf> = manifest.files("still").collect();
+        assert_eq!(files, [Path::new("IMG_2.jpg")]);
+        assert_eq!(manifest.files("unknown").count(), 0);
+    }
+
+    #[test]
+    fn insert_replaces_the_companions() {
+        let mut manifest = Manifest::default();
+        manifest.insert(
+            "live".to_string(),
+            PathBuf::from("IMG_1.jpg"),
+            vec![PathBuf::from("IMG_1.mov")],
+        );
+        manifest.insert("live".to_string(), PathBuf::from("IMG_1.jpg"), vec![]);
+
+        assert!(manifest.companions.is_empty());
+    }
+
+    #[test]
+    fn rename_follows_the_companions() {
+        let mut manifest = Manifest::default();
+        manifest.insert(
+            "live".to_string(),
+            PathBuf::from("IMG_1.jpg"),
+            vec![PathBuf::from("IMG_1.HEIC")],
+        );
+
+        manifest.rename(Path::new("IMG_1.HEIC"), Path::new("2023-07-01.HEIC"));
+
+        assert_eq!(
+            manifest.companions["live"],
+            [PathBuf::from("2023-07-01.HEIC")]
+        );
+    }
+
+    #[test]
+    fn loads_a_manifest_without_companions() {
+        let manifest: Manifest = serde_json::from_str(r#"{"items":{"id":"IMG_1.jpg"}}"#).unwrap();
+
+        assert_eq!(manifest.items["id"], Path::new("IMG_1.jpg"));
+        assert!(manifest.companions.is_empty());
+    }
+}
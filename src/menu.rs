@@ -0,0 +1,57 @@
+use anyhow::Result;
+use dialoguer::{theme::Theme, FuzzySelect, Select};
+
+/// Past this many items, [`select_filtered`] lets the user type to narrow them down.
+const FILTER_THRESHOLD: usize = 20;
+
+/// Shows a menu ending with a "Cancel" entry.
+///
+/// Returns the index of the chosen item, or `None` if the user cancelled, either through the
+/// "Cancel" entry or with 'Esc' or 'q'.
+pub fn select<T>(theme: &dyn Theme, prompt: &str, items: &[T]) -> Result<Option<usize>>
+where
+    T: ToString,
+{
+    select_with_exit(theme, prompt, items, "Cancel")
+}
+
+/// Shows a menu ending with an `exit` entry, like "Back" in a submenu.
+///
+/// Returns `None` when the `exit` entry is chosen, or with 'Esc' or 'q'.
+pub fn select_with_exit<T>(
+    theme: &dyn Theme,
+    prompt: &str,
+    items: &[T],
+    exit: &str,
+) -> Result<Option<usize>>
+where
+    T: ToString,
+{
+    let selection = Select::with_theme(theme)
+        .with_prompt(prompt)
+        .default(0)
+        .items(items)
+        .item(exit)
+        .interact_opt()?;
+
+    Ok(selection.filter(|&index| index < items.len()))
+}
+
+/// Like [`select`], but with many items, narrows them down as the user types part of a name.
+/// 'Esc' cancels, as typing 'q' only filters.
+///
+/// Returns the index of the chosen item among all the `items`.
+pub fn select_filtered<T>(theme: &dyn Theme, prompt: &str, items: &[T]) -> Result<Option<usize>>
+where
+    T: ToString,
+{
+    if items.len() <= FILTER_THRESHOLD {
+        return select(theme, prompt, items);
+    }
+
+    Ok(FuzzySelect::with_theme(theme)
+        .with_prompt(prompt)
+        .default(0)
+        .items(items)
+        .interact_opt()?)
+}
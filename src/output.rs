@@ -0,0 +1,89 @@
+#[cfg(feature = "interactive")]
+use console::Term;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "interactive")]
+use std::{
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Silences [`info!`] and [`warning!`], leaving only the errors.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Prints how things are going to stdout, unless quiet.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_quiet() {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Prints a warning to stderr, unless quiet.
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_quiet() {
+            eprintln!("Warning: {}", format_args!($($arg)*));
+        }
+    };
+}
+
+pub(crate) use {info, warning};
+
+#[cfg(feature = "interactive")]
+const SPINNER_FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+
+/// Shows a message with a spinner on stderr until dropped, so that slow requests don't look like
+/// the tool hung. Nothing is shown when quiet or without a terminal.
+#[cfg(feature = "interactive")]
+pub struct Spinner {
+    stop: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+#[cfg(feature = "interactive")]
+impl Spinner {
+    pub fn start(message: &str) -> Self {
+        let term = Term::stderr();
+        if is_quiet() || !term.is_term() {
+            return Self { stop: None };
+        }
+
+        let message = message.to_string();
+        let (sender, receiver) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            for frame in SPINNER_FRAMES.iter().cycle() {
+                let _ = term.clear_line();
+                let _ = term.write_str(&format!("{frame} {message}"));
+                match receiver.recv_timeout(Duration::from_millis(100)) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    _ => break,
+                }
+            }
+            let _ = term.clear_line();
+        });
+
+        Self {
+            stop: Some((sender, thread)),
+        }
+    }
+}
+
+#[cfg(feature = "interactive")]
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        if let Some((sender, thread)) = self.stop.take() {
+            let _ = sender.send(());
+            // Waits for the line to be cleared, before anything else is printed.
+            let _ = thread.join();
+        }
+    }
+}
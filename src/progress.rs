@@ -0,0 +1,135 @@
+use std::{
+    ops::AddAssign,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Transfer statistics shared by the concurrent downloads of an album.
+pub struct Progress {
+    started_at: Instant,
+    bytes: AtomicU64,
+    items: AtomicUsize,
+    failed: AtomicUsize,
+    retries: AtomicUsize,
+    exhausted: AtomicUsize,
+    total_items: Option<usize>,
+    /// The items downloaded by an earlier run, counted as done in the reports.
+    present: usize,
+}
+
+/// How many items of an album were downloaded, and how many couldn't be.
+#[derive(Default, Clone, Copy)]
+pub struct Tally {
+    pub downloaded: usize,
+    pub failed: usize,
+    /// How many downloads were tried again after a network error.
+    pub retries: usize,
+    /// Among the failed items, those still failing once out of retries.
+    pub exhausted: usize,
+}
+
+impl AddAssign for Tally {
+    fn add_assign(&mut self, other: Self) {
+        self.downloaded += other.downloaded;
+        self.failed += other.failed;
+        self.retries += other.retries;
+        self.exhausted += other.exhausted;
+    }
+}
+
+impl Progress {
+    pub fn new(total_items: Option<usize>) -> Self {
+        Self {
+            started_at: Instant::now(),
+            bytes: AtomicU64::new(0),
+            items: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+            retries: AtomicUsize::new(0),
+            exhausted: AtomicUsize::new(0),
+            total_items,
+            present: 0,
+        }
+    }
+
+    /// Starts the reports from `present` items, already there from an earlier run.
+    pub fn with_present(self, present: usize) -> Self {
+        Self { present, ..self }
+    }
+
+    pub fn add_bytes(&self, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn item_done(&self) {
+        self.items.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn item_failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn item_retried(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// An item failed after using all its retries, on top of [`Progress::item_failed`].
+    pub fn item_exhausted(&self) {
+        self.exhausted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn items(&self) -> usize {
+        self.items.load(Ordering::Relaxed)
+    }
+
+    pub fn tally(&self) -> Tally {
+        Tally {
+            downloaded: self.items(),
+            failed: self.failed.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            exhausted: self.exhausted.load(Ordering::Relaxed),
+        }
+    }
+
+    /// How many items are left to download, when the total is known.
+    pub fn remaining(&self) -> Option<usize> {
+        self.total_items
+            .map(|total_items| total_items.saturating_sub(self.present + self.items()))
+    }
+
+    /// Summarizes the speed so far, and how long the rest should take when the total is known.
+    pub fn report(&self) -> String {
+        let elapsed = self.started_at.elapsed();
+        let downloaded = self.items();
+        let megabytes = self.bytes.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let speed = megabytes / elapsed.as_secs_f64().max(f64::EPSILON);
+
+        // The earlier runs may have downloaded items that are downloaded again.
+        let items = match self.total_items {
+            Some(total_items) => (self.present + downloaded).min(total_items),
+            None => self.present + downloaded,
+        };
+
+        match self.total_items {
+            Some(total_items) if downloaded > 0 => {
+                let remaining = total_items.saturating_sub(items) as u32;
+                // From the speed of this run only.
+                let eta = elapsed / downloaded as u32 * remaining;
+                format!(
+                    "{speed:.1} MB/s, {items} of {total_items} items, about {} left",
+                    format_duration(eta)
+                )
+            }
+            Some(total_items) => format!("{speed:.1} MB/s, {items} of {total_items} items"),
+            None => format!("{speed:.1} MB/s, {items} items"),
+        }
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, seconds) => format!("{seconds}s"),
+        (0, minutes, seconds) => format!("{minutes}m {seconds:02}s"),
+        (hours, minutes, _) => format!("{hours}h {minutes:02}m"),
+    }
+}
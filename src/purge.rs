@@ -0,0 +1,89 @@
+use anyhow::Result;
+use dialoguer::{theme::Theme, Confirm};
+use directories::ProjectDirs;
+use std::{fs, path::PathBuf};
+
+use crate::{config::Configuration, manifest::MANIFEST_FILE, theme::ensure_interactive};
+
+/// Removes everything the tool stored on this computer, after confirmation.
+///
+/// The downloaded media is only removed if the user asks for it too. The manifests kept in its
+/// folders go with the configuration, as nothing would read them anymore.
+pub fn purge(project_dirs: &ProjectDirs, theme: &dyn Theme) -> Result<()> {
+    ensure_interactive()?;
+
+    let configuration = Configuration::load(project_dirs)?;
+    let media_folders: Vec<PathBuf> = configuration
+        .local_albums
+        .iter()
+        .map(|local_album| configuration.album_path(local_album))
+        .chain(std::iter::once(configuration.library_path()))
+        .filter(|folder| folder.exists())
+        .collect();
+    let manifests: Vec<PathBuf> = media_folders
+        .iter()
+        .map(|folder| folder.join(MANIFEST_FILE))
+        .filter(|manifest| manifest.exists())
+        .collect();
+
+    let mut folders: Vec<PathBuf> = vec![];
+    for folder in [
+        project_dirs.config_dir(),
+        project_dirs.data_dir(),
+        project_dirs.cache_dir(),
+    ] {
+        // Some platforms use the same folder for several of them.
+        if folder.exists() && !folders.iter().any(|known| known == folder) {
+            folders.push(folder.to_path_buf());
+        }
+    }
+
+    if folders.is_empty() && manifests.is_empty() {
+        println!("Nothing to remove");
+    } else {
+        println!("The following will be removed:");
+        for folder in &folders {
+            println!("{}", folder.display());
+            for entry in fs::read_dir(folder)? {
+                println!("  {}", entry?.path().display());
+            }
+        }
+        for manifest in &manifests {
+            println!("{}", manifest.display());
+        }
+
+        if Confirm::with_theme(theme)
+            .with_prompt("Remove the configuration, tokens, caches and manifests?")
+            .default(false)
+            .interact()?
+        {
+            for folder in &folders {
+                fs::remove_dir_all(folder)?;
+            }
+            for manifest in &manifests {
+                fs::remove_file(manifest)?;
+            }
+            println!("Removed");
+        }
+    }
+
+    if !media_folders.is_empty() {
+        println!("The downloaded media is in:");
+        for folder in &media_folders {
+            println!("{}", folder.display());
+        }
+
+        if Confirm::with_theme(theme)
+            .with_prompt("Remove the downloaded media too?")
+            .default(false)
+            .interact()?
+        {
+            for folder in &media_folders {
+                fs::remove_dir_all(folder)?;
+            }
+            println!("Removed");
+        }
+    }
+
+    Ok(())
+}
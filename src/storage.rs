@@ -0,0 +1,74 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Where the downloaded files end up, by their path relative to the folder of an album.
+///
+/// The files are downloaded into a local staging folder first, where they are named after their
+/// content and converted, before being stored.
+///
+/// Only [`LocalStorage`] implements it so far. A remote backend, like S3, would stage the files in
+/// a local temporary folder and upload them in [`StorageBackend::store`].
+pub trait StorageBackend: Send + Sync {
+    /// The local folder the files are downloaded into.
+    fn staging_folder(&self) -> &Path;
+
+    /// Moves a file out of the staging folder, to `key`.
+    fn store(&self, file: &Path, key: &Path) -> io::Result<()>;
+
+    fn exists(&self, key: &Path) -> bool;
+}
+
+/// Keeps the files in a local folder, which is its own staging folder.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new<P>(root: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Where the file of `key` goes, in the same extended-length form as the staged files.
+    fn path(&self, key: &Path) -> io::Result<PathBuf> {
+        let mut path = extended_length_path(&self.root)?;
+        // Pushed one by one, as extended-length paths don't take `/` for a separator.
+        path.extend(key.components());
+        Ok(path)
+    }
+}
+
+impl StorageBackend for LocalStorage {
+    fn staging_folder(&self) -> &Path {
+        &self.root
+    }
+
+    fn store(&self, file: &Path, key: &Path) -> io::Result<()> {
+        let target = self.path(key)?;
+        if file != target {
+            fs::rename(file, target)?;
+        }
+        Ok(())
+    }
+
+    fn exists(&self, key: &Path) -> bool {
+        self.path(key).is_ok_and(|path| path.exists())
+    }
+}
+
+/// On Windows, the existing `folder` in its extended-length `\\?\` form, for the files written
+/// deep in it not to run into `MAX_PATH`. Elsewhere, `folder` as is.
+pub fn extended_length_path(folder: &Path) -> io::Result<PathBuf> {
+    if cfg!(windows) {
+        // Canonical paths always come with the prefix on Windows.
+        fs::canonicalize(folder)
+    } else {
+        Ok(folder.to_path_buf())
+    }
+}
@@ -0,0 +1,101 @@
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::{fs, path::Path};
+
+/// Lists the file names not to download into the folder it is in, one pattern per line.
+pub const SYNCIGNORE_FILE: &str = ".syncignore";
+
+/// The patterns of a `.syncignore`, in the gitignore syntax: `*` matches any run of characters
+/// and `?` a single one, lines starting with `#` are comments, and `!` brings back the file names
+/// ignored by the patterns above it.
+///
+/// The patterns match the file names of the items on Google, folders don't come into it.
+pub struct SyncIgnore {
+    gitignore: Gitignore,
+}
+
+impl Default for SyncIgnore {
+    fn default() -> Self {
+        Self {
+            gitignore: Gitignore::empty(),
+        }
+    }
+}
+
+impl SyncIgnore {
+    /// Loads the `.syncignore` of `folder`, ignoring nothing if there is none.
+    pub fn load(folder: &Path) -> Result<Self> {
+        let file = folder.join(SYNCIGNORE_FILE);
+        if !file.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&file)
+            .with_context(|| format!("Couldn't read {}", file.display()))?;
+        Self::parse(folder, &content).with_context(|| format!("Invalid {}", file.display()))
+    }
+
+    fn parse(folder: &Path, content: &str) -> Result<Self> {
+        let mut builder = GitignoreBuilder::new(folder);
+        for line in content.lines() {
+            builder.add_line(None, line)?;
+        }
+
+        Ok(Self {
+            gitignore: builder.build()?,
+        })
+    }
+
+    /// Whether the last pattern matching `filename` ignores it.
+    pub fn is_ignored(&self, filename: &str) -> bool {
+        self.gitignore.matched(filename, false).is_ignore()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn syncignore(content: &str) -> SyncIgnore {
+        SyncIgnore::parse(Path::new("album"), content).unwrap()
+    }
+
+    #[test]
+    fn matches_the_wildcards() {
+        let syncignore = syncignore("*.gif\nIMG_????.jpg\n");
+
+        assert!(syncignore.is_ignored("animation.gif"));
+        assert!(syncignore.is_ignored("IMG_0001.jpg"));
+        assert!(!syncignore.is_ignored("IMG_01.jpg"));
+        assert!(!syncignore.is_ignored("photo.jpg"));
+    }
+
+    #[test]
+    fn skips_the_comments_and_blank_lines() {
+        let syncignore = syncignore("# *.jpg\n\n*.gif\n");
+
+        assert!(!syncignore.is_ignored("# *.jpg"));
+        assert!(!syncignore.is_ignored("photo.jpg"));
+        assert!(syncignore.is_ignored("animation.gif"));
+    }
+
+    #[test]
+    fn negation_brings_back_a_file_name() {
+        let syncignore = syncignore("Screenshot*\n!Screenshot_keep.png\n");
+
+        assert!(syncignore.is_ignored("Screenshot_1.png"));
+        assert!(!syncignore.is_ignored("Screenshot_keep.png"));
+    }
+
+    #[test]
+    fn the_last_matching_pattern_wins() {
+        let syncignore = syncignore("!Screenshot_keep.png\nScreenshot*\n");
+
+        assert!(syncignore.is_ignored("Screenshot_keep.png"));
+    }
+
+    #[test]
+    fn ignores_nothing_without_patterns() {
+        assert!(!SyncIgnore::default().is_ignored("photo.jpg"));
+    }
+}
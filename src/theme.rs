@@ -0,0 +1,37 @@
+#[cfg(feature = "interactive")]
+use anyhow::{bail, Result};
+#[cfg(feature = "interactive")]
+use dialoguer::theme::{ColorfulTheme, SimpleTheme, Theme};
+use std::io::IsTerminal;
+
+/// The look of the interactive menus.
+#[derive(Clone, Copy, clap::ArgEnum)]
+pub enum ThemeChoice {
+    Colorful,
+    /// No colors, for terminals where the colorful theme renders poorly.
+    Simple,
+}
+
+#[cfg(feature = "interactive")]
+impl ThemeChoice {
+    pub fn theme(self) -> Box<dyn Theme> {
+        match self {
+            ThemeChoice::Colorful => Box::new(ColorfulTheme::default()),
+            ThemeChoice::Simple => Box::new(SimpleTheme),
+        }
+    }
+}
+
+pub fn is_interactive() -> bool {
+    std::io::stderr().is_terminal()
+}
+
+/// Fails when the menus can't be shown, as they would otherwise wait forever for a key press.
+#[cfg(feature = "interactive")]
+pub fn ensure_interactive() -> Result<()> {
+    if !is_interactive() {
+        bail!("This action needs an interactive terminal");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,205 @@
+use anyhow::{bail, Result};
+use directories::ProjectDirs;
+use futures::TryStreamExt;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    album::COVER_FILE,
+    api::Id,
+    args::Cli,
+    audit::AuditLog,
+    client::get_api,
+    config::Configuration,
+    index::{INDEX_FILE, PLAYLIST_FILE},
+    item::{download_file, sanitize_file_name, DownloadOptions, Item, Outcome},
+    list_items,
+    manifest::{Manifest, MANIFEST_FILE},
+    progress::Progress,
+    storage::LocalStorage,
+    syncignore::SyncIgnore,
+    Source,
+};
+
+/// Checks every configured album against Google, listing the items missing locally and the local
+/// files that aren't in the album anymore. With `repair`, the missing items are downloaded.
+///
+/// Fails if items are still missing in the end, for scripts to notice.
+pub async fn verify(project_dirs: &ProjectDirs, cli: &Cli, repair: bool) -> Result<()> {
+    let configuration = Configuration::load(project_dirs)?;
+    let api = get_api().await?;
+    let options = DownloadOptions {
+        write_metadata: cli.write_metadata,
+        folder_template: cli.folder_template.clone(),
+        sequence_prefix: cli.sequence_prefix,
+        convert_heic: cli.convert_heic,
+        keep_heic: cli.keep_heic,
+        live_photos: cli.live_photos,
+        unknown_media: cli.unknown_media,
+        audit: Some(Arc::new(AuditLog::open(project_dirs)?)),
+        ..Default::default()
+    };
+
+    let mut still_missing = 0;
+    for local_album in &configuration.local_albums {
+        let path = configuration.album_path(local_album);
+        let mut manifest = Manifest::load(&path)?;
+        let items: Vec<Item> =
+            list_items(api, Source::Album(&local_album.album_id), &Mutex::new(None))
+                .try_collect()
+                .await?;
+        let local_files = media_files(&path)?;
+        let syncignore = SyncIgnore::load(&path)?;
+
+        adopt_untracked_files(&mut manifest, &items, &local_files);
+
+        // Along with their position in the album, counting from 1.
+        let missing: Vec<(usize, &Item)> = (1..)
+            .zip(&items)
+            .filter(|(_, item)| {
+                !syncignore.is_ignored(item.filename())
+                    && !manifest
+                        .items
+                        .get(item.id().as_str())
+                        .is_some_and(|file| local_files.contains(file))
+            })
+            .collect();
+        let expected: BTreeSet<&PathBuf> = items
+            .iter()
+            .flat_map(|item| manifest.files(item.id().as_str()))
+            .collect();
+        let extra: Vec<&PathBuf> = local_files
+            .iter()
+            .filter(|file| !expected.contains(file))
+            .collect();
+
+        println!(
+            "{}: {} items, {} missing, {} extra",
+            local_album.name,
+            items.len(),
+            missing.len(),
+            extra.len()
+        );
+        for file in &extra {
+            println!("  extra {}", file.display());
+        }
+        // The base URLs expire after an hour, listing a large album can take about as long.
+        let fresh_items: HashMap<String, Item> = if repair && !missing.is_empty() {
+            let ids: Vec<Id> = missing.iter().map(|(_, item)| item.id().clone()).collect();
+            api.batch_get(&ids)
+                .await?
+                .into_iter()
+                .map(Item::from_media_item)
+                .map(|item| (item.id().to_string(), item))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        for (position, item) in missing {
+            let item = fresh_items.get(item.id().as_str()).unwrap_or(item);
+            if !repair {
+                println!("  missing {} ({})", item.filename(), item.id());
+                still_missing += 1;
+                continue;
+            }
+
+            let options = DownloadOptions {
+                quality: local_album.quality.unwrap_or_default(),
+                ..options.clone()
+            };
+            let storage = LocalStorage::new(&path);
+            let outcome =
+                download_file(item, position, &storage, &options, &Progress::new(None)).await;
+            if let Some(audit) = &options.audit {
+                audit.record(item, &path, &outcome);
+            }
+            match outcome {
+                Ok(Outcome::Downloaded { file, companions }) => {
+                    println!("  downloaded {}", file.display());
+                    manifest.insert(item.id().to_string(), file, companions);
+                }
+                Ok(Outcome::Skipped) => still_missing += 1,
+                Err(e) => {
+                    eprintln!("Error: couldn't download {}: {e:#}", item.filename());
+                    still_missing += 1;
+                }
+            }
+        }
+
+        manifest.save(&path)?;
+    }
+
+    if still_missing > 0 {
+        bail!("{still_missing} items are missing from the mirror");
+    }
+
+    Ok(())
+}
+
+/// Records in the manifest the files downloaded before it existed, recognized by their original
+/// name. Renamed photos can't be told apart, they stay missing.
+fn adopt_untracked_files(manifest: &mut Manifest, items: &[Item], local_files: &BTreeSet<PathBuf>) {
+    let mut claimed: BTreeSet<PathBuf> = manifest
+        .items
+        .values()
+        .chain(manifest.companions.values().flatten())
+        .cloned()
+        .collect();
+    for item in items {
+        let is_tracked = manifest
+            .items
+            .get(item.id().as_str())
+            .is_some_and(|file| local_files.contains(file));
+        if is_tracked {
+            continue;
+        }
+
+        let file_name = sanitize_file_name(item.filename());
+        let untracked = local_files.iter().find(|file| {
+            !claimed.contains(*file) && file.file_name().is_some_and(|name| *name == *file_name)
+        });
+        if let Some(file) = untracked {
+            claimed.insert(file.clone());
+            manifest.items.insert(item.id().to_string(), file.clone());
+        }
+    }
+}
+
+/// The media files of `folder` and its subfolders, relative to it, leaving out the files written
+/// alongside the media: sidecars, covers, indexes and manifests.
+fn media_files(folder: &Path) -> Result<BTreeSet<PathBuf>> {
+    fn visit(folder: &Path, relative: &Path, files: &mut BTreeSet<PathBuf>) -> Result<()> {
+        for entry in fs::read_dir(folder)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name_str = name.to_string_lossy();
+            if name_str.starts_with('.') {
+                continue;
+            }
+
+            let path = entry.path();
+            if path.is_dir() {
+                visit(&path, &relative.join(&name), files)?;
+            } else if !name_str.ends_with(".json")
+                && ![COVER_FILE, INDEX_FILE, PLAYLIST_FILE].contains(&name_str.as_ref())
+            {
+                files.insert(relative.join(&name));
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut files = BTreeSet::new();
+    if folder.is_dir() {
+        visit(folder, Path::new(""), &mut files)?;
+    }
+    // Hidden already, but better safe than reporting it.
+    files.remove(Path::new(MANIFEST_FILE));
+
+    Ok(files)
+}